@@ -0,0 +1,62 @@
+// the tree walker against the bytecode interpreter on the same programs, `cargo bench`
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use text_flow::tf_vm::env::Env;
+use text_flow::text_flow::ExprsParser;
+use text_flow::tf_vm::builtins::init_builtin;
+use text_flow::tf_vm::bytecode::{compile, run};
+use text_flow::tf_vm::runtimes::RuntimeValue;
+use text_flow::tf_vm::vm::eval;
+
+const PROGRAMS: [(&str, &str); 5] = [
+    ("arithmetic", "n = 0; [1, 2, 3, 4, 5, 6, 7, 8] >- {n = n + i * 2 - 1}; n"),
+    ("calls", "fib = f[n]{ n < 2 && n || fib[n - 1] + fib[n - 2] }; fib[15]"),
+    ("fields", "o = {n: 0, xs: [1, 2, 3],}; [1, 2, 3, 4, 5, 6, 7, 8] >- {o.n = o.n + o.xs.(i - i / 3 * 3)}; o.n"),
+    ("lists", "[1, 2, 3, 4, 5, 6, 7, 8] -< f[x]{[x, x * x]} -< f[p]{p.1 - p.0} >- list"),
+    ("strings", "['a', 'bb', 'ccc', 'dddd'] -< f[s]{s + '-' + s.repr[]} >- list"),
+];
+
+// runs `f` for about `budget`, and gives the time one run takes
+fn time(budget: Duration, mut f: impl FnMut()) -> Duration {
+    f();
+    let start = Instant::now();
+    let mut runs = 0;
+    while start.elapsed() < budget {
+        f();
+        runs += 1;
+    }
+    start.elapsed() / runs
+}
+
+fn main() {
+    // `cargo bench -- calls` runs only the programs with `calls` in their name
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    let budget = Duration::from_millis(500);
+    let root = init_builtin();
+    println!("{:<12} {:>14} {:>14} {:>8}", "program", "tree walker", "bytecode", "speedup");
+    for (name, code) in PROGRAMS {
+        if filter.as_ref().is_some_and(|filter| !name.contains(filter.as_str())) {
+            continue;
+        }
+        let ast = ExprsParser::new().parse(code).unwrap();
+        // a program that stops at an error early would time nothing
+        let value = eval(Env::new(Some(Arc::clone(&root))), ast.clone());
+        assert!(!matches!(*value, RuntimeValue::Error(_)), "{name}: {}", value.repr());
+        assert_eq!(run(Env::new(Some(Arc::clone(&root))), &compile(&ast)).repr(), value.repr(), "{name}");
+        // each run gets a fresh env over the same builtins, so only the program itself is timed
+        let walked = time(budget, || {
+            eval(Env::new(Some(Arc::clone(&root))), ast.clone());
+        });
+        // compiled once, as `Program::parse` does, and run as often as the tree walker
+        let chunk = compile(&ast);
+        let compiled = time(budget, || {
+            run(Env::new(Some(Arc::clone(&root))), &chunk);
+        });
+        println!(
+            "{name:<12} {:>14} {:>14} {:>7.2}x",
+            format!("{walked:?}"),
+            format!("{compiled:?}"),
+            walked.as_secs_f64() / compiled.as_secs_f64()
+        );
+    }
+}
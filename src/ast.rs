@@ -47,6 +47,10 @@ pub enum Expr {
     Value(Value),
     // [a, b, c]
     List(Vec<Box<Expr>>),
+    // *rest
+    Unpack(Box<Expr>),
+    // {a: 1, b}
+    Object(Vec<(Box<String>, Box<Expr>)>),
     // abc.xyz
     Get { from: Box<Expr>, key: Box<Expr>, is_expr: bool, weak: bool },
     ExprWithCodePos { exp: Box<Expr>, start: usize, end: usize },
@@ -58,4 +62,23 @@ pub enum Expr {
     // x ? y : z
     Op3 { op: Op, x: Box<Expr>, y: Box<Expr>, z: Box<Expr> },
     Control(Control),
+    // import 'path.tf' as name
+    Import { path: Box<String>, alias: Option<Box<String>> },
+    // match x { [a, b] => a, _ => x }
+    Match { value: Box<Expr>, arms: Vec<MatchArm> },
+    // n: str
+    Typed { name: Box<String>, type_name: Box<String> },
+    // type Name(Parent) { init = f[self]{..} }
+    TypeDef { name: Box<String>, parent: Option<Box<Expr>>, body: Box<Expr> },
+    // BEGIN { n = 0 }, only at the top of a program
+    Begin(Box<Expr>),
+    // END { print[n] }, only at the top of a program
+    End(Box<Expr>),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct MatchArm {
+    pub pattern: Box<Expr>,
+    pub guard: Option<Box<Expr>>,
+    pub body: Box<Expr>,
 }
@@ -0,0 +1,134 @@
+pub const USAGE: &str = "\
+usage: text-flow [options] [script.tf] [args...]
+
+  script.tf       run the program in this file, or the one read from stdin for -
+  -i              start an interactive session, as does running with no arguments
+  -e <program>    run the program given on the command line
+  -f <file>       run the program in <file>, like script.tf
+  -n              run the program once per input line, with the line in `$0`,
+                  its fields in `$1`, `$2`.. and NR, NF, FNR and FILENAME set
+  -p              like -n, and print what the program gives for each line
+  -F <sep>        split fields on <sep>, a string or a /regex/ (default: blanks)
+  -R <sep>        split input into records on <sep>, a string, a /regex/, or ''
+                  for paragraphs separated by blank lines (default: line breaks)
+  -h, --help      show this help
+  -V, --version   show the version
+
+Everything after the program is passed to it as `args`, and `input` streams
+the records of those files, or of stdin when there are none.
+
+exit status: 0 on success, 1 on a runtime error, 2 on a usage or parse error
+";
+
+#[derive(Debug, PartialEq)]
+pub enum Program {
+    Inline(String),
+    File(String),
+    Stdin,
+}
+
+#[derive(Debug, PartialEq, Default)]
+pub struct Options {
+    pub each_line: bool,
+    pub print: bool,
+    pub field_separator: Option<String>,
+    pub record_separator: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run {
+        program: Program,
+        args: Vec<String>,
+        options: Options,
+    },
+    Repl,
+    Help,
+    Version,
+}
+
+// options come before the program, anything after it belongs to the script
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+    if args.peek().is_none() {
+        return Ok(Command::Repl);
+    }
+    let mut options = Options::default();
+    let program = loop {
+        let arg = args.next().ok_or("no program given")?;
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-i" => return Ok(Command::Repl),
+            "-n" => options.each_line = true,
+            "-p" => {
+                options.each_line = true;
+                options.print = true;
+            }
+            "-F" => options.field_separator = Some(args.next().ok_or("-F needs an argument")?),
+            "-R" => options.record_separator = Some(args.next().ok_or("-R needs an argument")?),
+            // awk's attached form, as in -F:
+            option if option.starts_with("-F") => options.field_separator = Some(option[2..].to_string()),
+            "-e" => break Program::Inline(args.next().ok_or("-e needs an argument")?),
+            "-f" => break file(args.next().ok_or("-f needs an argument")?),
+            "--" => break file(args.next().ok_or("no program given")?),
+            option if option.starts_with('-') && option != "-" => return Err(format!("unknown option {option}")),
+            _ => break file(arg),
+        }
+    };
+    Ok(Command::Run { program, args: args.collect(), options })
+}
+
+// `-` is stdin, as for the input files
+fn file(path: String) -> Program {
+    if path == "-" { Program::Stdin } else { Program::File(path) }
+}
+
+#[test]
+fn test_parse_args() {
+    let parse = |args: &[&str]| parse_args(args.iter().map(|a| a.to_string()));
+    assert_eq!(parse(&["a.tf", "x.log", "-e"]), Ok(Command::Run {
+        program: Program::File("a.tf".to_string()),
+        args: vec!["x.log".to_string(), "-e".to_string()],
+        options: Options::default(),
+    }));
+    assert_eq!(parse(&["-e", "1 + 1", "x"]), Ok(Command::Run {
+        program: Program::Inline("1 + 1".to_string()),
+        args: vec!["x".to_string()],
+        options: Options::default(),
+    }));
+    assert_eq!(parse(&["-f", "a.tf"]), Ok(Command::Run {
+        program: Program::File("a.tf".to_string()),
+        args: vec![],
+        options: Options::default(),
+    }));
+    assert_eq!(parse(&["-p", "-F:", "-e", "$1"]), Ok(Command::Run {
+        program: Program::Inline("$1".to_string()),
+        args: vec![],
+        options: Options { each_line: true, print: true, field_separator: Some(":".to_string()), record_separator: None },
+    }));
+    assert_eq!(parse(&["-n", "-F", "/,\\s*/", "a.tf"]), Ok(Command::Run {
+        program: Program::File("a.tf".to_string()),
+        args: vec![],
+        options: Options { each_line: true, print: false, field_separator: Some("/,\\s*/".to_string()), record_separator: None },
+    }));
+    assert_eq!(parse(&["-R", "", "-e", "$0"]), Ok(Command::Run {
+        program: Program::Inline("$0".to_string()),
+        args: vec![],
+        options: Options { record_separator: Some("".to_string()), ..Options::default() },
+    }));
+    assert_eq!(parse(&["-", "x.log"]), Ok(Command::Run {
+        program: Program::Stdin,
+        args: vec!["x.log".to_string()],
+        options: Options::default(),
+    }));
+    assert_eq!(parse(&["-f", "-"]), Ok(Command::Run { program: Program::Stdin, args: vec![], options: Options::default() }));
+    assert_eq!(parse(&["--version", "a.tf"]), Ok(Command::Version));
+    assert_eq!(parse(&["-h"]), Ok(Command::Help));
+    assert_eq!(parse(&[]), Ok(Command::Repl));
+    assert_eq!(parse(&["-i"]), Ok(Command::Repl));
+    assert!(parse(&["-n"]).is_err());
+    assert!(parse(&["-e"]).is_err());
+    assert!(parse(&["-x", "a.tf"]).is_err());
+    assert!(parse(&["-n", "-F"]).is_err());
+}
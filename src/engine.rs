@@ -0,0 +1,284 @@
+use std::fmt::{Display, Formatter};
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, RwLock};
+use crate::ast::Expr;
+use crate::text_flow::ProgramParser;
+use crate::tf_vm::builtins::init_builtin;
+use crate::tf_vm::call::{native, CallContext};
+use crate::tf_vm::convert::IntoRuntime;
+use crate::tf_vm::env::Env;
+use crate::tf_vm::error::RuntimeError;
+use crate::tf_vm::limits::{with_limits, Cancelled, CancellationToken, LimitExceeded, Limits};
+use crate::tf_vm::records::{compile_sections, run_compiled, run_compiled_records, CompiledSections, LineMode};
+use crate::tf_vm::runtimes::RuntimeValue;
+use derivative::Derivative;
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    // the source isn't a program, at a 1-based line and column
+    Parse { line: usize, column: usize, message: String },
+    // the program raised an error and nothing caught it
+    Runtime(RuntimeError),
+    // the program ran past one of the engine's limits
+    Limit(LimitExceeded),
+    // the engine's cancellation token was triggered while the program ran
+    Cancelled,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse { line, column, message } => write!(f, "{line}:{column}: parse error: {message}"),
+            Error::Runtime(e) => write!(f, "{e}"),
+            Error::Limit(e) => write!(f, "{e}"),
+            Error::Cancelled => write!(f, "{Cancelled}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// parsed and compiled once, a program can run any number of times, on any engine
+#[derive(Derivative)]
+#[derivative(Debug, Clone)]
+pub struct Program {
+    ast: Vec<Box<Expr>>,
+    #[derivative(Debug = "ignore")]
+    sections: Arc<CompiledSections>,
+}
+
+impl Program {
+    pub fn parse(source: &str) -> Result<Program> {
+        ProgramParser::new().parse(source).map(|ast| Program { sections: Arc::new(compile_sections(ast.clone())), ast }).map_err(|e| {
+            let offset = match &e {
+                lalrpop_util::ParseError::InvalidToken { location } => *location,
+                lalrpop_util::ParseError::UnrecognizedEOF { location, expected: _ } => *location,
+                lalrpop_util::ParseError::UnrecognizedToken { token: (start, _, _), expected: _ } => *start,
+                lalrpop_util::ParseError::ExtraToken { token: (start, _, _) } => *start,
+                lalrpop_util::ParseError::User { error: _ } => 0,
+            };
+            let (line, column) = line_col(source, offset);
+            Error::Parse { line, column, message: e.to_string() }
+        })
+    }
+
+    pub fn ast(&self) -> &[Box<Expr>] {
+        &self.ast
+    }
+}
+
+// 1-based line and column of a byte offset
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+    (line, col)
+}
+
+// the builtins and the globals set so far, which every program run on the engine shares
+pub struct Engine {
+    global: Arc<RwLock<Env>>,
+    limits: Limits,
+    token: CancellationToken,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine { global: Env::new(Some(init_builtin())), limits: Limits::default(), token: CancellationToken::new() }
+    }
+
+    pub fn parse(&self, source: &str) -> Result<Program> {
+        Program::parse(source)
+    }
+
+    // BEGIN, the program, then END; the value is the last one, or END's
+    pub fn eval(&self, program: &Program) -> Result<RuntimeValue> {
+        catch(|| with_limits(&self.limits, &self.token, || *run_compiled(Arc::clone(&self.global), &program.sections)))
+    }
+
+    // the program once per record of `files`, or of stdin without them
+    pub fn eval_records(&self, program: &Program, files: Vec<String>, mode: &LineMode) -> Result<RuntimeValue> {
+        catch(|| with_limits(&self.limits, &self.token, || *run_compiled_records(Arc::clone(&self.global), &program.sections, files, mode)))
+    }
+
+    // applies to each evaluation on its own, the budget starts over every time
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    // the token every evaluation on the engine checks, hand a clone to whatever should be able to stop them
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    pub fn run(&self, source: &str) -> Result<RuntimeValue> {
+        self.eval(&self.parse(source)?)
+    }
+
+    pub fn set(&self, name: &str, value: impl IntoRuntime) {
+        self.global.write().unwrap().set(name.to_string(), value.into_runtime());
+    }
+
+    // a global, or a builtin when no global hides it
+    pub fn get(&self, name: &str) -> Option<RuntimeValue> {
+        self.global.read().unwrap().get(name.to_string())
+    }
+
+    // a native function programs can call by `name`, `body` may hold whatever state the host gives it
+    pub fn register<F>(&self, name: &str, parameters: &[&str], body: F)
+        where F: Fn(&CallContext) -> crate::tf_vm::error::Result<RuntimeValue> + Send + Sync + 'static {
+        self.set(name, native(self.env(), parameters, body));
+    }
+
+    pub fn env(&self) -> Arc<RwLock<Env>> {
+        Arc::clone(&self.global)
+    }
+}
+
+// limits and cancellation unwind out of the program, anything else unwinding is a bug and goes on
+fn catch(run: impl FnOnce() -> RuntimeValue) -> Result<RuntimeValue> {
+    match std::panic::catch_unwind(AssertUnwindSafe(run)) {
+        Ok(RuntimeValue::Error(e)) => Err(Error::Runtime(e)),
+        Ok(value) => Ok(value),
+        Err(payload) if payload.is::<Cancelled>() => Err(Error::Cancelled),
+        Err(payload) if payload.is::<LimitExceeded>() => Err(Error::Limit(payload.downcast_ref::<LimitExceeded>().unwrap().clone())),
+        Err(payload) => std::panic::resume_unwind(payload),
+    }
+}
+
+#[test]
+fn test_line_col() {
+    assert_eq!(line_col("a = 1;\nb = ", 0), (1, 1));
+    assert_eq!(line_col("a = 1;\nb = ", 11), (2, 5));
+}
+
+#[test]
+fn test_engine() {
+    let engine = Engine::new();
+    let program = engine.parse("n = n + 1; n").unwrap();
+    engine.set("n", RuntimeValue::Int64(1));
+    assert!(matches!(engine.eval(&program), Ok(RuntimeValue::Int64(2))));
+    assert!(matches!(engine.eval(&program), Ok(RuntimeValue::Int64(3))));
+    assert!(matches!(engine.get("n"), Some(RuntimeValue::Int64(3))));
+    assert!(matches!(engine.parse("a = 1;\nb = "), Err(Error::Parse { line: 2, column: 4, message: _ })));
+    assert!(matches!(engine.run("read['/no/such/file']"), Err(Error::Runtime(_))));
+    assert_eq!(engine.run("undefined_name").unwrap_err().to_string(), "name error: `undefined_name` is not defined");
+}
+
+#[test]
+fn test_register() {
+    let engine = Engine::new();
+    let calls = Arc::new(std::sync::atomic::AtomicI64::new(0));
+    let counter = Arc::clone(&calls);
+    engine.register("tick", &["by"], move |ctx| {
+        let by = ctx.arg::<Option<i64>>("by")?.unwrap_or(1);
+        Ok(RuntimeValue::Int64(counter.fetch_add(by, std::sync::atomic::Ordering::SeqCst) + by))
+    });
+    assert!(matches!(engine.run("tick[]; tick[by=5]"), Ok(RuntimeValue::Int64(6))));
+    assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 6);
+    // a global named like a parameter isn't an argument
+    assert!(matches!(engine.run("by = 42; tick[]"), Ok(RuntimeValue::Int64(7))));
+    assert_eq!(engine.run("tick['x']").unwrap_err().to_string(), "type error: `by`: expected an i64, not 'x'");
+}
+
+#[test]
+fn test_conversions() {
+    use std::collections::{BTreeMap, HashMap};
+    use crate::tf_vm::convert::{deserialize, serialize, FromRuntime};
+    let engine = Engine::new();
+    engine.set("names", vec!["ann", "bob"]);
+    engine.set("ages", HashMap::from([("ann".to_string(), 31), ("bob".to_string(), 42)]));
+    engine.set("missing", None::<i64>);
+    let names: Vec<String> = FromRuntime::from_runtime(engine.run("names -< {i + '!'} >- list").unwrap()).unwrap();
+    assert_eq!(names, ["ann!", "bob!"]);
+    assert_eq!(i64::from_runtime(engine.run("ages.bob + 1").unwrap()).unwrap(), 43);
+    assert_eq!(Option::<i64>::from_runtime(engine.run("missing").unwrap()).unwrap(), None);
+    assert_eq!(
+        HashMap::<String, Vec<i64>>::from_runtime(engine.run("{a: [1, 2]}").unwrap()).unwrap(),
+        HashMap::from([("a".to_string(), vec![1, 2])])
+    );
+    assert_eq!(u8::from_runtime(engine.run("300").unwrap()).unwrap_err().to_string(), "type error: expected a u8, not 300");
+
+    engine.set("config", serialize(&BTreeMap::from([("retries", (3, Some(0.5))), ("timeout", (10, None))])).unwrap());
+    assert_eq!(engine.run("[config.retries.0, config.timeout.1]").unwrap().to_string(), "[3, none]");
+    let config: BTreeMap<String, (i64, Option<f64>)> = deserialize(&engine.get("config").unwrap()).unwrap();
+    assert_eq!(config["retries"], (3, Some(0.5)));
+    assert!(deserialize::<Vec<String>>(&engine.run("[1]").unwrap()).is_err());
+
+    // values json can't hold keep their form
+    let big = serialize(&(f64::INFINITY, u64::MAX, i128::MIN)).unwrap();
+    assert_eq!(big.to_string(), format!("[inf, {}, {}]", u64::MAX, i128::MIN));
+    assert_eq!(deserialize::<(f64, u64, i128)>(&big).unwrap(), (f64::INFINITY, u64::MAX, i128::MIN));
+    assert!(deserialize::<f64>(&serialize(&f64::NAN).unwrap()).unwrap().is_nan());
+    let results = serialize(&[Ok::<i64, String>(1), Err("no".to_string())]).unwrap();
+    engine.set("results", results.clone());
+    assert_eq!(engine.run("[results.0.Ok, results.1.Err]").unwrap().to_string(), "[1, 'no']");
+    assert_eq!(
+        deserialize::<Vec<std::result::Result<i64, String>>>(&results).unwrap(),
+        [Ok(1), Err("no".to_string())]
+    );
+    assert_eq!(
+        deserialize::<serde_json::Value>(&engine.run("a = {x: 1,}; a.x = a; a").unwrap()).unwrap_err().to_string(),
+        "value error: can't read an object that contains itself"
+    );
+}
+
+#[test]
+fn test_limits() {
+    use std::time::Duration;
+    let mut engine = Engine::new();
+    engine.set("ones", crate::tf_vm::iter::to_runtime_iter(std::iter::repeat_with(|| Ok(RuntimeValue::Int64(1)))));
+    engine.set_limits(Limits { steps: Some(1000), ..Limits::default() });
+    assert!(matches!(engine.run("n = 5; try[f[]{ones -< {i + 1} >- f[x]{x}}, f[e]{0}]"), Err(Error::Limit(LimitExceeded::Steps(1000)))));
+    // what ran before the limit stays, and the engine is still usable
+    assert!(matches!(engine.get("n"), Some(RuntimeValue::Int64(5))));
+    assert!(matches!(engine.run("ones.take[2] >- list"), Ok(RuntimeValue::List(_))));
+    // a native iter drained by a native method evaluates nothing, but still runs out of steps
+    assert!(matches!(engine.run("ones.count[]"), Err(Error::Limit(LimitExceeded::Steps(1000)))));
+
+    engine.set_limits(Limits { depth: Some(10), ..Limits::default() });
+    assert!(matches!(engine.run("down = f[x]{down[x + 1]}; down[0]"), Err(Error::Limit(LimitExceeded::Depth(10)))));
+    assert!(matches!(engine.run("ok = f[x]{x}; ok[ok[ok[1]]]"), Ok(RuntimeValue::Int64(1))));
+
+    engine.set_limits(Limits { value_size: Some(1 << 16), ..Limits::default() });
+    assert!(matches!(engine.run("'x' * 100000000000"), Err(Error::Limit(LimitExceeded::ValueSize(_)))));
+    assert!(matches!(engine.run("ones.take[20].reduce[f[s, x]{s + s}, 'x']"), Err(Error::Limit(LimitExceeded::ValueSize(_)))));
+    assert!(matches!(engine.run("ones -< {'x'} >- list"), Err(Error::Limit(LimitExceeded::ValueSize(_)))));
+    assert!(matches!(engine.run("'x' * 100"), Ok(RuntimeValue::String(_))));
+    // an object is as big as its fields, and one that holds itself is still sized
+    assert!(matches!(engine.run("o = f[]{ {s: 'x' * 30000,} }; [o[], o[], o[]]"), Err(Error::Limit(LimitExceeded::ValueSize(_)))));
+    assert!(matches!(engine.run("a = {x: 1,}; a.x = a; [a]"), Ok(RuntimeValue::List(_))));
+
+    // many values each under the size limit still add up
+    engine.set_limits(Limits { memory: Some(1 << 20), ..Limits::default() });
+    assert!(matches!(engine.run("keep = {xs: [],}; ones -< {keep.xs = keep.xs + ['x' * 1000]} >- f[x]{x}"), Err(Error::Limit(LimitExceeded::Memory(_)))));
+    assert!(matches!(engine.run("ones.take[100] -< {'x' * 1000} >- list"), Ok(RuntimeValue::List(_))));
+
+    engine.set_limits(Limits { timeout: Some(Duration::from_millis(50)), ..Limits::default() });
+    assert!(matches!(engine.run("ones.count[]"), Err(Error::Limit(LimitExceeded::Timeout(_)))));
+    assert!(matches!(engine.run("ones -< {i + 1} >- f[x]{x}"), Err(Error::Limit(LimitExceeded::Timeout(_)))));
+}
+
+#[test]
+fn test_cancel() {
+    let engine = Engine::new();
+    engine.set("ones", crate::tf_vm::iter::to_runtime_iter(std::iter::repeat_with(|| Ok(RuntimeValue::Int64(1)))));
+    let token = engine.cancellation_token();
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        token.cancel();
+    });
+    assert!(matches!(engine.run("try[f[]{ones -< {i + 1} >- f[x]{x}}, f[e]{0}]"), Err(Error::Cancelled)));
+    canceller.join().unwrap();
+    assert!(matches!(engine.run("ones.count[]"), Err(Error::Cancelled)));
+    engine.cancellation_token().reset();
+    assert!(matches!(engine.run("ones.take[3].count[]"), Ok(RuntimeValue::Int64(3))));
+}
@@ -0,0 +1,17 @@
+#![allow(clippy::box_collection, clippy::vec_box, clippy::enum_variant_names, clippy::upper_case_acronyms)]
+
+#[macro_use]
+extern crate lalrpop_util;
+
+use crate::ast::Expr;
+use crate::tf_vm::env::Env;
+lalrpop_mod!(#[allow(clippy::all)] pub text_flow);
+pub mod ast;
+pub mod engine;
+pub mod utils;
+pub mod tf_vm;
+mod test;
+
+pub use crate::engine::{Engine, Error, Program};
+pub use crate::tf_vm::call::CallContext;
+pub use crate::tf_vm::limits::{CancellationToken, Limits};
@@ -1,23 +1,104 @@
-#[macro_use]
-extern crate lalrpop_util;
-extern crate core;
+use std::io::Read;
+use text_flow::Engine;
+use text_flow::tf_vm::io::{input_records, read_records, stdin};
+use text_flow::tf_vm::records::{FieldSeparator, LineMode, RecordSeparator};
+use text_flow::tf_vm::runtimes::RuntimeValue;
+use crate::cli::{Command, Options, Program, USAGE};
+mod cli;
+mod repl;
 
-use crate::ast::Expr;
-use tf_vm::vm::VM;
-use crate::tf_vm::env::Env;
-use crate::tf_vm::builtins::init_builtin;
-lalrpop_mod!(pub text_flow);
-mod ast;
-mod utils;
-mod tf_vm;
-mod test;
+const RUNTIME_ERROR: i32 = 1;
+const USAGE_ERROR: i32 = 2;
+
+fn run(program: Program, args: Vec<String>, options: Options) -> i32 {
+    let record_separator = match RecordSeparator::parse(options.record_separator.as_deref().unwrap_or("\n")) {
+        Ok(separator) => separator,
+        Err(e) => {
+            eprintln!("text-flow: {e}");
+            return USAGE_ERROR;
+        }
+    };
+    let line_mode = if options.each_line {
+        let separator = match FieldSeparator::parse(options.field_separator.as_deref().unwrap_or(" ")) {
+            Ok(separator) => separator,
+            Err(e) => {
+                eprintln!("text-flow: {e}");
+                return USAGE_ERROR;
+            }
+        };
+        Some(LineMode { separator, record_separator: record_separator.clone(), print: options.print })
+    } else {
+        None
+    };
+    let (name, source) = match program {
+        Program::Inline(source) => ("-e".to_string(), source),
+        Program::File(file) => match std::fs::read_to_string(&file) {
+            Ok(source) => (file, source),
+            Err(e) => {
+                eprintln!("text-flow: can't read '{file}': {e}");
+                return USAGE_ERROR;
+            }
+        },
+        Program::Stdin => {
+            let mut source = String::new();
+            if let Err(e) = stdin().read_to_string(&mut source) {
+                eprintln!("text-flow: can't read the program from stdin: {e}");
+                return USAGE_ERROR;
+            }
+            ("-".to_string(), source)
+        }
+    };
+    let engine = Engine::new();
+    let program = match engine.parse(&source) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{name}:{e}");
+            return USAGE_ERROR;
+        }
+    };
+    let files = args.clone();
+    // without files the input is stdin, read through the same buffer as the `stdin` builtin
+    let input = match (args.is_empty(), &record_separator) {
+        (true, RecordSeparator::Newline) => engine.get("stdin").unwrap(),
+        (true, _) => read_records(stdin(), record_separator),
+        (false, _) => input_records(files.clone(), record_separator),
+    };
+    engine.set("args", args);
+    engine.set("input", input);
+    // imports are relative to the script's directory, or to the current one without a script file
+    if name != "-e" && name != "-" {
+        engine.set("__file__", name);
+    }
+    let result = match &line_mode {
+        Some(line_mode) => engine.eval_records(&program, files, line_mode),
+        None => engine.eval(&program),
+    };
+    match result {
+        // a flow ending in a sink or its own end of stream has nothing left to show
+        Ok(RuntimeValue::EOF | RuntimeValue::None) => 0,
+        Ok(value) => {
+            println!("{value}");
+            0
+        }
+        Err(e) => {
+            eprintln!("text-flow: {e}");
+            RUNTIME_ERROR
+        }
+    }
+}
 
 fn main() {
-    let parser = text_flow::ExprsParser::new();
-    let vm = VM::new();
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input).unwrap();
-    let ast: Result<Vec<Box<Expr>>, _> = parser.parse(input.as_str());
-    let global = init_builtin();
-    println!("{:#?}", vm.eval(global, ast.unwrap()))
+    let command = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprint!("text-flow: {e}\n\n{USAGE}");
+            std::process::exit(USAGE_ERROR)
+        }
+    };
+    match command {
+        Command::Repl => std::process::exit(repl::run()),
+        Command::Help => print!("{USAGE}"),
+        Command::Version => println!("text-flow {}", env!("CARGO_PKG_VERSION")),
+        Command::Run { program, args, options } => std::process::exit(run(program, args, options)),
+    }
 }
@@ -0,0 +1,278 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock, RwLock};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Editor, Helper};
+use text_flow::{Engine, Program};
+use text_flow::text_flow::ProgramParser;
+use text_flow::tf_vm::env::Env;
+use text_flow::tf_vm::limits::CancellationToken;
+use text_flow::tf_vm::runtimes::{get_value_type_name, RuntimeValue};
+use text_flow::tf_vm::vm::remove_code_pos;
+
+const HELP: &str = "\
+:type <expr>    show the type of what <expr> gives
+:ast <expr>     show how <expr> parses
+:env            list the variables defined so far
+:help           show this help
+:quit           leave, as does ctrl-d
+";
+
+const META_COMMANDS: [&str; 5] = [":type", ":ast", ":env", ":help", ":quit"];
+
+// what ctrl-c cancels while an input is evaluated
+static INTERRUPT: OnceLock<CancellationToken> = OnceLock::new();
+
+#[cfg(unix)]
+fn cancel_on_interrupt(token: CancellationToken) {
+    use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+    extern "C" fn interrupt(_: std::ffi::c_int) {
+        if let Some(token) = INTERRUPT.get() {
+            token.cancel();
+        }
+    }
+    let _ = INTERRUPT.set(token);
+    let action = SigAction::new(SigHandler::Handler(interrupt), SaFlags::empty(), SigSet::empty());
+    // the handler only stores to an atomic, which is safe to do from a signal handler
+    if let Err(e) = unsafe { sigaction(Signal::SIGINT, &action) } {
+        eprintln!("text-flow: ctrl-c will end the session rather than the input: {e}");
+    }
+}
+
+#[cfg(not(unix))]
+fn cancel_on_interrupt(_: CancellationToken) {}
+
+struct ReplHelper {
+    env: Arc<RwLock<Env>>,
+}
+
+// one engine for the whole session, so definitions carry over from one input to the next
+pub fn run() -> i32 {
+    let engine = Engine::new();
+    engine.set("args", Vec::<String>::new());
+    engine.set("input", engine.get("stdin").unwrap());
+    let token = engine.cancellation_token();
+    cancel_on_interrupt(token.clone());
+    let mut editor: Editor<ReplHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("text-flow: can't start the repl: {e}");
+            return 1;
+        }
+    };
+    editor.set_helper(Some(ReplHelper { env: engine.env() }));
+    let history = history_file();
+    if let Some(history) = &history {
+        // there's no history yet on the first run
+        let _ = editor.load_history(history);
+    }
+    println!("text-flow {}, :help for help", env!("CARGO_PKG_VERSION"));
+    loop {
+        match editor.readline(">> ") {
+            Ok(line) => {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let _ = editor.add_history_entry(line.as_str());
+                // a ctrl-c at the prompt doesn't carry over to the next input
+                token.reset();
+                if !respond(&engine, line.trim()) {
+                    break;
+                }
+            }
+            // ctrl-c drops the current input or stops its evaluation, ctrl-d leaves
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("text-flow: {e}");
+                break;
+            }
+        }
+    }
+    if let Some(history) = &history {
+        if let Err(e) = editor.save_history(history) {
+            eprintln!("text-flow: can't save history to '{}': {e}", history.display());
+        }
+    }
+    0
+}
+
+fn history_file() -> Option<PathBuf> {
+    std::env::var_os("TEXT_FLOW_HISTORY").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".text_flow_history")))
+}
+
+// false once the session should end
+fn respond(engine: &Engine, input: &str) -> bool {
+    let (command, source) = split_command(input);
+    match command {
+        Some(":quit") => return false,
+        Some(":help") => print!("{HELP}"),
+        Some(":env") => print!("{}", show_env(&engine.env())),
+        Some(":ast") => if let Some(program) = parse(engine, source) {
+            for expr in program.ast() {
+                println!("{:#?}", remove_code_pos(expr.clone()));
+            }
+        },
+        Some(":type") => if let Some(value) = parse(engine, source).and_then(|program| eval(engine, &program)) {
+            println!("{}", get_value_type_name(&value));
+        },
+        Some(command) => eprintln!("unknown command {command}, :help lists them"),
+        None => match parse(engine, source).and_then(|program| eval(engine, &program)) {
+            Some(RuntimeValue::EOF | RuntimeValue::None) | None => {}
+            Some(value) => println!("{value}"),
+        },
+    }
+    true
+}
+
+fn split_command(input: &str) -> (Option<&str>, &str) {
+    if !input.starts_with(':') {
+        return (None, input);
+    }
+    match input.split_once(char::is_whitespace) {
+        Some((command, rest)) => (Some(command), rest.trim_start()),
+        None => (Some(input), ""),
+    }
+}
+
+fn parse(engine: &Engine, source: &str) -> Option<Program> {
+    engine.parse(source).map_err(|e| eprintln!("{e}")).ok()
+}
+
+// errors are shown and leave the session going
+fn eval(engine: &Engine, program: &Program) -> Option<RuntimeValue> {
+    engine.eval(program).map_err(|e| eprintln!("text-flow: {e}")).ok()
+}
+
+fn show_env(env: &Arc<RwLock<Env>>) -> String {
+    let variables = env.read().unwrap().variables();
+    let mut names: Vec<&String> = variables.keys().collect();
+    names.sort();
+    names.into_iter().map(|name| format!("{name} = {}\n", variables[name])).collect()
+}
+
+// input ending inside a `{`, `[` or `(` goes on over the next lines
+fn is_incomplete(source: &str) -> bool {
+    let source = split_command(source).1;
+    let unexpected_end = matches!(ProgramParser::new().parse(source), Err(lalrpop_util::ParseError::UnrecognizedEOF { .. }));
+    unexpected_end && open_brackets(source) > 0
+}
+
+fn open_brackets(source: &str) -> i32 {
+    let mut depth = 0;
+    let mut quote = None;
+    for c in source.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '{' | '[' | '(') => depth += 1,
+            (None, '}' | ']' | ')') => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+fn is_name_char(c: char) -> bool {
+    c == '$' || c == '_' || c.is_alphanumeric()
+}
+
+fn word_start(line: &str, pos: usize) -> usize {
+    line[..pos].char_indices().rev().take_while(|(_, c)| is_name_char(*c)).last().map_or(pos, |(i, _)| i)
+}
+
+impl ReplHelper {
+    // names from the env, or after `x.` the fields and methods of x
+    fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        if line.starts_with(':') && !line[..pos].contains(char::is_whitespace) {
+            return (0, META_COMMANDS.iter().filter(|c| c.starts_with(&line[..pos])).map(|c| c.to_string()).collect());
+        }
+        let start = word_start(line, pos);
+        let word = &line[start..pos];
+        let names = if let Some(before) = line[..start].strip_suffix('.') {
+            let receiver = &before[word_start(before, before.len())..];
+            let value = self.env.read().unwrap().get(receiver.to_string());
+            value.map(|value| self.member_names(&value)).unwrap_or_default()
+        } else {
+            self.env.read().unwrap().names()
+        };
+        (start, names.into_iter().filter(|name| name.starts_with(word)).collect())
+    }
+
+    fn member_names(&self, value: &RuntimeValue) -> BTreeSet<String> {
+        match value {
+            RuntimeValue::RuntimeType(t) => t.get_env().read().unwrap().names(),
+            // an object's env sits over the root, so only its own fields count,
+            // and an instance's type adds its methods
+            RuntimeValue::WithEnv { value, env } => {
+                let mut names: BTreeSet<String> = env.read().unwrap().variables().into_keys().collect();
+                if let RuntimeValue::RuntimeType(t) = value.as_ref() {
+                    names.extend(t.get_env().read().unwrap().names());
+                }
+                names
+            }
+            RuntimeValue::EOF | RuntimeValue::Error(_) => BTreeSet::new(),
+            value => match self.env.read().unwrap().get(get_value_type_name(value)) {
+                Some(RuntimeValue::RuntimeType(t)) => t.get_env().read().unwrap().names(),
+                _ => BTreeSet::new(),
+            },
+        }
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(if is_incomplete(ctx.input()) { ValidationResult::Incomplete } else { ValidationResult::Valid(None) })
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+#[test]
+fn test_is_incomplete() {
+    assert!(is_incomplete("g = f[x]{"));
+    assert!(is_incomplete("[1,\n2,"));
+    assert!(is_incomplete(":type [1,"));
+    assert!(!is_incomplete("g = f[x]{x * 2}"));
+    assert!(!is_incomplete("'{' + "));
+    assert!(!is_incomplete("1 +"));
+    assert!(!is_incomplete("1 )"));
+}
+
+#[test]
+fn test_candidates() {
+    let engine = Engine::new();
+    engine.run("total = 1; point = {x: 1, y: 2}").unwrap();
+    let helper = ReplHelper { env: engine.env() };
+    assert_eq!(helper.candidates("1 + tot", 7), (4, vec!["total".to_string()]));
+    assert_eq!(helper.candidates("point.", 6), (6, vec!["x".to_string(), "y".to_string()]));
+    assert!(helper.candidates("'a'.spl", 7).1.is_empty());
+    assert_eq!(helper.candidates("total.ty", 8), (6, vec!["type".to_string()]));
+    assert_eq!(helper.candidates(":ty", 3), (0, vec![":type".to_string()]));
+    assert_eq!(split_command(":ast  1 + 2"), (Some(":ast"), "1 + 2"));
+    assert_eq!(show_env(&helper.env), format!("point = {}\ntotal = 1\n", helper.env.read().unwrap().get("point".to_string()).unwrap()));
+    // an instance offers its fields and its type's methods
+    engine.run("type T { init = f[self]{ self.n = 1 } }; t = T[]").unwrap();
+    assert_eq!(helper.candidates("t.", 2), (2, vec!["init".to_string(), "n".to_string(), "repr".to_string(), "str".to_string(), "type".to_string()]));
+}
@@ -3,9 +3,9 @@ mod tests {
     use crate::ast::Op::{Add, Ge, Gt, Mul};
     use crate::ast::{Value};
     use crate::Expr;
-    use crate::Expr::{ExprWithCodePos, FuncCall, Get, Op2, Variable};
+    use crate::Expr::{ExprWithCodePos, FuncCall, Get, Import, Op2, Variable};
     use crate::utils::b;
-    lalrpop_mod!(pub text_flow);
+    use crate::text_flow;
 
     #[test]
     fn parse() {
@@ -208,7 +208,8 @@ mod tests {
                                 end: 1
                             }),
                             key: b(Variable(b("b".to_string()))),
-                            is_expr: false
+                            is_expr: false,
+                            weak: false
                         }),
                         start: 0,
                         end: 3
@@ -245,6 +246,20 @@ mod tests {
                     }
                 )
             ]),
+            ("import 'a.tf' as b", vec![
+                b(ExprWithCodePos {
+                    exp: b(ExprWithCodePos {
+                        exp: b(Import {
+                            path: b("a.tf".to_string()),
+                            alias: Some(b("b".to_string())),
+                        }),
+                        start: 0,
+                        end: 18
+                    }),
+                    start: 0,
+                    end: 18
+                })
+            ]),
         ];
         for (expr, should_ast) in cases {
             assert_eq!(parser.parse(expr).unwrap(), should_ast);
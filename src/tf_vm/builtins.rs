@@ -1,66 +1,117 @@
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 use crate::{Env};
-use crate::tf_vm::runtimes::{BuiltinOrExpr, RuntimeType, RuntimeValue};
-use crate::tf_vm::utils::{get_name_from_env, get_self_from_env, set_name_from_env};
+use crate::tf_vm::call::native;
+use crate::tf_vm::convert::IntoRuntime;
+use crate::tf_vm::error::RuntimeError;
+use crate::tf_vm::csv::init_csv;
+use crate::tf_vm::io::init_io;
+use crate::tf_vm::iter::{iter_methods, to_runtime_iter};
+use crate::tf_vm::json::init_json;
+use crate::tf_vm::modules::init_modules;
+use crate::tf_vm::runtimes::{RuntimeType, RuntimeValue};
+use crate::tf_vm::utils::set_name_from_env;
 use crate::utils::b;
 
+pub fn get_type_method(env: Arc<RwLock<Env>>) -> RuntimeValue {
+    native(env, &["self"], |ctx| Ok(RuntimeValue::String(b(ctx.value("self").get_type(ctx.env()).name()))))
+}
+
+pub fn get_str_method(env: Arc<RwLock<Env>>) -> RuntimeValue {
+    native(env, &["self"], |ctx| Ok(RuntimeValue::String(b(ctx.value("self").to_string()))))
+}
+
+pub fn get_repr_method(env: Arc<RwLock<Env>>) -> RuntimeValue {
+    native(env, &["self"], |ctx| Ok(RuntimeValue::String(b(ctx.value("self").repr()))))
+}
+
+fn error_object(e: &RuntimeError) -> RuntimeValue {
+    RuntimeValue::WithEnv {
+        env: Env::from(HashMap::from([
+            ("kind".to_string(), RuntimeValue::String(b(e.kind().to_string()))),
+            ("message".to_string(), RuntimeValue::String(b(e.message()))),
+        ]), None),
+        value: b(RuntimeValue::None),
+    }
+}
+
 pub fn init_builtin() -> Arc<RwLock<Env>> {
     let env = Env::empty();
-    let gen_get_type = || ("type".to_string(), RuntimeValue::FuncDef {
-        parameters: vec![b("self".to_string())],
-        body: BuiltinOrExpr::Builtin(|env| {
-            RuntimeValue::String(b(get_self_from_env(env.clone()).unwrap().get_type(env).name()))
-        }),
-        env: env.clone(),
-    });
+    let gen_get_type = || ("type".to_string(), get_type_method(env.clone()));
+    let gen_str = || ("str".to_string(), get_str_method(env.clone()));
+    let gen_repr = || ("repr".to_string(), get_repr_method(env.clone()));
     env.write().unwrap().update_variables(HashMap::from([
         ("fun".to_string(), RuntimeValue::RuntimeType(
             RuntimeType::FuncDef {
-                env: Env::empty(),
+                env: Env::from(HashMap::from([
+                    gen_get_type(),
+                    gen_str(),
+                    gen_repr()
+                ]), None)
+            }
+        )),
+        ("none".to_string(), RuntimeValue::RuntimeType(
+            RuntimeType::None {
+                env: Env::from(HashMap::from([
+                    gen_get_type(),
+                    gen_str(),
+                    gen_repr()
+                ]), None)
             }
         )),
+        ("bool".to_string(), RuntimeValue::RuntimeType(
+            RuntimeType::Bool {
+                env: Env::from(HashMap::from([
+                    gen_get_type(),
+                    gen_str(),
+                    gen_repr()
+                ]), None)
+            }
+        )),
+        ("true".to_string(), RuntimeValue::Bool(true)),
+        ("false".to_string(), RuntimeValue::Bool(false)),
         ("i64".to_string(), RuntimeValue::RuntimeType(
             RuntimeType::Int64 {
                 env: Env::from(HashMap::from([
-                    ("str".to_string(), RuntimeValue::FuncDef {
-                        parameters: vec![b("self".to_string())],
-                        body: BuiltinOrExpr::Builtin(|env| match get_self_from_env(env).unwrap() {
-                            RuntimeValue::Int64(i) => RuntimeValue::String(b(i.to_string())),
-                            _ => panic!("internal error, should only be i64")
-                        }),
-                        env: env.clone(),
-                    }),
-                    gen_get_type()
+                    gen_get_type(),
+                    gen_str(),
+                    gen_repr()
                 ]), None)
             }
         )),
         ("i128".to_string(), RuntimeValue::RuntimeType(
             RuntimeType::Int128 {
                 env: Env::from(HashMap::from([
-                    ("str".to_string(), RuntimeValue::FuncDef {
-                        parameters: vec![b("self".to_string())],
-                        body: BuiltinOrExpr::Builtin(|env| match get_self_from_env(env).unwrap() {
-                            RuntimeValue::Int128(i) => RuntimeValue::String(b(i.to_string())),
-                            _ => panic!("internal error, should only be i128")
-                        }),
-                        env: env.clone(),
-                    }),
-                    gen_get_type()
+                    gen_get_type(),
+                    gen_str(),
+                    gen_repr()
+                ]), None)
+            }
+        )),
+        ("float".to_string(), RuntimeValue::RuntimeType(
+            RuntimeType::Float {
+                env: Env::from(HashMap::from([
+                    gen_get_type(),
+                    gen_str(),
+                    gen_repr()
                 ]), None)
             }
         )),
         ("str".to_string(), RuntimeValue::RuntimeType(
             RuntimeType::String {
                 env: Env::from(HashMap::from([
-                    gen_get_type()
+                    gen_get_type(),
+                    gen_str(),
+                    gen_repr()
                 ]), None)
             }
         )),
         ("reg".to_string(), RuntimeValue::RuntimeType(
             RuntimeType::Regex {
                 env: Env::from(HashMap::from([
-                    gen_get_type()
+                    gen_get_type(),
+                    gen_str(),
+                    gen_repr()
                 ]), None)
             }
         )),
@@ -68,74 +119,45 @@ pub fn init_builtin() -> Arc<RwLock<Env>> {
             RuntimeType::List {
                 env: Env::from(HashMap::from([
                     gen_get_type(),
-                    ("len".to_string(), RuntimeValue::FuncDef {
-                        parameters: vec![b("self".to_string())],
-                        body: BuiltinOrExpr::Builtin(
-                            |env| match get_self_from_env(env).unwrap() {
-                                RuntimeValue::List(list) => RuntimeValue::Int128(
-                                    list.len() as i128
-                                ),
-                                _ => panic!("only list have len")
-                            }
-                        ),
-                        env: env.clone(),
-                    }),
-                    ("iter".to_string(), RuntimeValue::FuncDef {
-                        parameters: vec![b("self".to_string())],
-                        body: BuiltinOrExpr::Builtin(
-                            |env| {
-                                let self_value = get_self_from_env(env.clone()).unwrap();
-                                if let RuntimeValue::List(_) = self_value {
-                                    RuntimeValue::WithEnv {
-                                        env: Env::from(HashMap::from([
-                                            ("next".to_string(), RuntimeValue::FuncDef {
-                                                parameters: vec![b("self".to_string())],
-                                                body: BuiltinOrExpr::Builtin(
-                                                    |env| {
-                                                        let self_value = get_self_from_env(env.clone()).unwrap();
-                                                        if let RuntimeValue::WithEnv { env, value } = self_value {
-                                                            if let RuntimeValue::Int64(current) = get_name_from_env(env.clone(), "current".to_string()).unwrap_or(RuntimeValue::Int64(0)) {
-                                                                if let RuntimeValue::List(list) = *value {
-                                                                    set_name_from_env(env, "current".to_string(), RuntimeValue::Int64(current + 1));
-                                                                    if (current as usize) < list.len() {
-                                                                        *list[current as usize].clone()
-                                                                    } else {
-                                                                        RuntimeValue::EOF
-                                                                    }
-                                                                } else {
-                                                                    panic!("must be list")
-                                                                }
-                                                            } else {
-                                                                panic!("must be int64")
-                                                            }
-                                                        } else {
-                                                            panic!("must be obj")
-                                                        }
-                                                    }
-                                                ),
-                                                env: env.clone(),
-                                            })
-                                        ]), None),
-                                        value: b(self_value),
-                                    }
-                                } else {
-                                    panic!("only list can, not {self_value:?}")
-                                }
-                            },
-                        ),
-                        env: env.clone(),
-                    })
+                    gen_str(),
+                    gen_repr(),
+                    ("len".to_string(), native(env.clone(), &["self"], |ctx| match ctx.value("self") {
+                        RuntimeValue::List(list) => Ok(list.len().into_runtime()),
+                        self_value => Err(RuntimeError::Type(format!("only a list has a len, not {}", self_value.repr())))
+                    })),
+                    ("iter".to_string(), native(env.clone(), &["self"], |ctx| match ctx.value("self") {
+                        RuntimeValue::List(list) => Ok(to_runtime_iter(list.into_iter().map(|v| Ok(*v)))),
+                        self_value => Err(RuntimeError::Type(format!("only a list can be iterated here, not {}", self_value.repr())))
+                    }))
                 ]), None)
             }
         )),
-        ("obj".to_string(), RuntimeValue::FuncDef {
-            parameters: vec![b("value".to_string())],
-            body: BuiltinOrExpr::Builtin(|env| RuntimeValue::WithEnv {
-                env: Env::new(Some(env.clone())),
-                value: b(get_name_from_env(env, "value".to_string()).unwrap_or(RuntimeValue::None)),
-            }),
-            env: env.clone(),
-        })
+        ("iter".to_string(), RuntimeValue::RuntimeType(
+            RuntimeType::Iter {
+                env: Env::from(HashMap::from_iter(
+                    [gen_get_type(), gen_str(), gen_repr()].into_iter().chain(iter_methods(env.clone()))
+                ), None)
+            }
+        )),
+        ("modules".to_string(), init_modules()),
+        ("obj".to_string(), native(env.clone(), &["value"], |ctx| Ok(RuntimeValue::WithEnv {
+            env: Env::new(Some(ctx.env())),
+            value: b(ctx.value("value")),
+        }))),
+        // calls `body`, and `handler` with {kind, message} if it raised an error
+        ("try".to_string(), native(env.clone(), &["body", "handler"], |ctx| {
+            match ctx.call(&ctx.value("body"), vec![]) {
+                Err(e) => match ctx.get("handler") {
+                    Some(handler) => ctx.call(&handler, vec![error_object(&e)]),
+                    None => Ok(RuntimeValue::None)
+                },
+                value => value
+            }
+        }))
     ]));
+    for (name, value) in init_io(env.clone()).into_iter().chain(init_csv(env.clone())) {
+        set_name_from_env(env.clone(), name, value);
+    }
+    set_name_from_env(env.clone(), "json".to_string(), init_json(env.clone()));
     env
 }
@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use crate::ast::{Control, Op, Value};
+use crate::Expr;
+use crate::tf_vm::env::Env;
+use crate::tf_vm::iter::RuntimeIter;
+use crate::tf_vm::limits;
+use crate::tf_vm::runtimes::{BuiltinOrExpr, RuntimeValue};
+use crate::tf_vm::vm::{
+    call_result, eval, runtime_arithmetic, runtime_assign, runtime_call, runtime_collect, runtime_comparison, runtime_equality,
+    runtime_get_field, runtime_index, runtime_load, runtime_map, runtime_object, runtime_op1, runtime_set_field, without_code_pos,
+    Argument,
+};
+use crate::utils::b;
+
+// the operands are indexes into the tables of the chunk the instruction is in
+#[derive(Debug, Clone)]
+pub enum Instr {
+    Const(usize),
+    None,
+    // by name through the env chain, as the tree walker does; closures, methods and record envs all
+    // share the env a function was made in, so a local isn't resolved to a slot
+    Load(usize),
+    // pops a value into a variable and pushes none, which is what an assignment gives
+    Store(usize),
+    // pops an object, then the value for its field, `a.b = ..`
+    SetField(usize),
+    // the same for a pattern, `[a, b] = ..`, which runs without evaluating anything of its own
+    Assign(usize),
+    Pop,
+    // pops that many values
+    List(usize),
+    // pops one value per field name
+    Object(usize),
+    Arithmetic(Op),
+    Equality(Op),
+    Comparison(Op),
+    Unary(Op),
+    // a jump keeps the value it tested, without a jump it's popped
+    JumpIfFalsy(usize),
+    JumpIfTruthy(usize),
+    Func(usize),
+    Field { name: usize, weak: bool },
+    // pops the index, then the list, `xs.(i)` or `xs.1`
+    Index { is_expr: bool },
+    // pops the arguments, then the function
+    Call(usize),
+    // pops a value and turns it into the source of the next `Map` or `Collect`
+    Source,
+    Map,
+    Collect,
+    Break,
+    // whatever has no instructions of its own is left to the tree walker
+    Eval(usize),
+}
+
+// a program compiled once, with its constants resolved, to run as often as needed
+#[derive(Default)]
+pub struct Chunk {
+    code: Vec<Instr>,
+    constants: Vec<RuntimeValue>,
+    names: Vec<String>,
+    exprs: Vec<Box<Expr>>,
+    funcs: Vec<(Vec<Box<String>>, Arc<Chunk>)>,
+    // the names of the arguments of each call, none for a positional one
+    calls: Vec<Vec<Option<String>>>,
+    objects: Vec<Vec<String>>,
+}
+
+// the value of the last expression, as `eval` gives for the same expressions
+pub fn compile(exprs: &[Box<Expr>]) -> Chunk {
+    let mut chunk = Chunk::default();
+    chunk.block(exprs);
+    chunk
+}
+
+impl Chunk {
+    fn emit(&mut self, instr: Instr) -> usize {
+        self.code.push(instr);
+        self.code.len() - 1
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        match self.names.iter().position(|n| n == name) {
+            Some(i) => i,
+            None => {
+                self.names.push(name.to_string());
+                self.names.len() - 1
+            }
+        }
+    }
+
+    fn fallback(&mut self, expr: &Expr) {
+        self.exprs.push(b(expr.clone()));
+        self.emit(Instr::Eval(self.exprs.len() - 1));
+    }
+
+    fn func(&mut self, parameters: Vec<Box<String>>, body: &[Box<Expr>]) {
+        self.funcs.push((parameters, Arc::new(compile(body))));
+        self.emit(Instr::Func(self.funcs.len() - 1));
+    }
+
+    fn block(&mut self, exprs: &[Box<Expr>]) {
+        if exprs.is_empty() {
+            self.emit(Instr::None);
+        }
+        for (i, expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.emit(Instr::Pop);
+            }
+            self.expr(expr);
+        }
+    }
+
+    // a jump to the next instruction emitted
+    fn patch(&mut self, jump: usize) {
+        let target = self.code.len();
+        match &mut self.code[jump] {
+            Instr::JumpIfFalsy(to) | Instr::JumpIfTruthy(to) => *to = target,
+            _ => unreachable!()
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::ExprWithCodePos { exp, start: _, end: _ } => self.expr(exp),
+            Expr::Block(block) => self.block(block),
+            Expr::Value(value) => {
+                self.constants.push(match value {
+                    Value::String(string) => RuntimeValue::String(string.clone()),
+                    Value::Int64(int64) => RuntimeValue::Int64(*int64),
+                    Value::Int128(int128) => RuntimeValue::Int128(*int128),
+                    Value::Regex(regex) => RuntimeValue::Regex(regex.clone()),
+                });
+                self.emit(Instr::Const(self.constants.len() - 1));
+            }
+            Expr::Variable(name) => {
+                let name = self.name(name);
+                self.emit(Instr::Load(name));
+            }
+            Expr::List(items) if !items.iter().any(|i| matches!(without_code_pos(i), Expr::Unpack(_))) => {
+                for item in items {
+                    self.expr(item);
+                }
+                self.emit(Instr::List(items.len()));
+            }
+            Expr::Object(fields) => {
+                for (_, value) in fields {
+                    self.expr(value);
+                }
+                self.objects.push(fields.iter().map(|(key, _)| key.to_string()).collect());
+                self.emit(Instr::Object(self.objects.len() - 1));
+            }
+            Expr::Op2 { op: Op::Assign, x, y } => {
+                self.expr(y);
+                match without_code_pos(x) {
+                    Expr::Variable(name) if name.as_str() == "_" => {
+                        self.emit(Instr::Pop);
+                        self.emit(Instr::None);
+                    }
+                    Expr::Variable(name) => {
+                        let name = self.name(name);
+                        self.emit(Instr::Store(name));
+                    }
+                    target => match field_target(target) {
+                        Some((from, name)) => {
+                            self.expr(from);
+                            let name = self.name(name);
+                            self.emit(Instr::SetField(name));
+                        }
+                        None => {
+                            self.exprs.push(x.clone());
+                            self.emit(Instr::Assign(self.exprs.len() - 1));
+                        }
+                    },
+                }
+            }
+            Expr::Op2 { op: op @ (Op::Add | Op::Sub | Op::Mul | Op::Div), x, y } => {
+                self.expr(x);
+                self.expr(y);
+                self.emit(Instr::Arithmetic(op.clone()));
+            }
+            Expr::Op2 { op: op @ (Op::Eq | Op::Ne), x, y } => {
+                self.expr(x);
+                self.expr(y);
+                self.emit(Instr::Equality(op.clone()));
+            }
+            Expr::Op2 { op: op @ (Op::Gt | Op::Ge | Op::Lt | Op::Le), x, y } => {
+                self.expr(x);
+                self.expr(y);
+                self.emit(Instr::Comparison(op.clone()));
+            }
+            Expr::Op2 { op: op @ (Op::And | Op::Or), x, y } => {
+                self.expr(x);
+                let jump = self.emit(if *op == Op::And { Instr::JumpIfFalsy(0) } else { Instr::JumpIfTruthy(0) });
+                self.expr(y);
+                self.patch(jump);
+            }
+            Expr::Op2 { op: op @ (Op::Map | Op::Collect), x, y } => {
+                self.expr(x);
+                self.emit(Instr::Source);
+                // a bare block is a function of the implicit `i`
+                match without_code_pos(y) {
+                    Expr::Block(block) => self.func(vec![], block),
+                    _ => self.expr(y),
+                }
+                self.emit(if *op == Op::Map { Instr::Map } else { Instr::Collect });
+            }
+            Expr::FuncDef { parameters, body } => self.func(parameters.clone(), std::slice::from_ref(body)),
+            Expr::Get { from, key, is_expr, weak } if !matches!(without_code_pos(from), Expr::Variable(name) if name.as_str() == "super") => {
+                match (is_expr, without_code_pos(key)) {
+                    (false, Expr::Variable(name)) => {
+                        self.expr(from);
+                        let name = self.name(name);
+                        self.emit(Instr::Field { name, weak: *weak });
+                    }
+                    (true, _) | (false, Expr::Value(_)) => {
+                        self.expr(from);
+                        self.expr(key);
+                        self.emit(Instr::Index { is_expr: *is_expr });
+                    }
+                    _ => self.fallback(expr),
+                }
+            }
+            Expr::FuncCall { func, arguments } if arguments.iter().all(|a| named_argument(a).is_some()) => {
+                self.expr(func);
+                let mut names = Vec::with_capacity(arguments.len());
+                for argument in arguments {
+                    let (name, value) = named_argument(argument).unwrap();
+                    names.push(name);
+                    self.expr(value);
+                }
+                self.calls.push(names);
+                self.emit(Instr::Call(self.calls.len() - 1));
+            }
+            Expr::Op1 { op, x } => {
+                self.expr(x);
+                self.emit(Instr::Unary(op.clone()));
+            }
+            Expr::Control(Control::Break) => {
+                self.emit(Instr::Break);
+            }
+            // match, import, type definitions and anything the tree walker rejects
+            expr => self.fallback(expr),
+        }
+    }
+}
+
+// `from.name` as an assignment target
+fn field_target(target: &Expr) -> Option<(&Expr, &str)> {
+    match target {
+        Expr::Get { from, key, is_expr: false, weak: _ } => match without_code_pos(key) {
+            Expr::Variable(name) => Some((from, name)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// `name=value` or a positional value, none for a target that can't be named
+fn named_argument(argument: &Expr) -> Option<(Option<String>, &Expr)> {
+    match without_code_pos(argument) {
+        Expr::Op2 { op: Op::Assign, x, y } => match without_code_pos(x) {
+            Expr::Variable(name) => Some((Some(name.to_string()), y)),
+            _ => None
+        },
+        argument => Some((None, argument)),
+    }
+}
+
+// runs `chunk` in `env`; like `eval`, an EOF or an error ends it as soon as anything gives one
+pub fn run(env: Arc<RwLock<Env>>, chunk: &Chunk) -> Box<RuntimeValue> {
+    let mut stack: Vec<Box<RuntimeValue>> = Vec::with_capacity(16);
+    let mut sources: Vec<RuntimeIter> = vec![];
+    let mut pc = 0;
+    while pc < chunk.code.len() {
+        limits::step();
+        let value = match &chunk.code[pc] {
+            Instr::Const(i) => b(chunk.constants[*i].clone()),
+            Instr::None => b(RuntimeValue::None),
+            Instr::Load(name) => b(runtime_load(&env, &chunk.names[*name])),
+            Instr::Store(name) => {
+                let value = stack.pop().unwrap();
+                env.write().unwrap().set(chunk.names[*name].clone(), *value);
+                b(RuntimeValue::None)
+            }
+            Instr::SetField(name) => {
+                let from = stack.pop().unwrap();
+                let value = stack.pop().unwrap();
+                runtime_set_field(*from, Some(&chunk.names[*name]), *value)
+            }
+            Instr::Assign(target) => {
+                let value = stack.pop().unwrap();
+                runtime_assign(Arc::clone(&env), &chunk.exprs[*target], *value)
+            }
+            Instr::Pop => {
+                stack.pop();
+                pc += 1;
+                continue;
+            }
+            Instr::List(n) => {
+                let list = RuntimeValue::List(stack.split_off(stack.len() - n));
+                limits::check_value(&list);
+                b(list)
+            }
+            Instr::Object(i) => {
+                let names = &chunk.objects[*i];
+                let values = stack.split_off(stack.len() - names.len());
+                runtime_object(&env, names.iter().cloned().zip(values.into_iter().map(|v| *v)).collect())
+            }
+            Instr::Arithmetic(op) => {
+                let y = stack.pop().unwrap();
+                let x = stack.pop().unwrap();
+                runtime_arithmetic(&env, op, x, y)
+            }
+            Instr::Equality(op) => {
+                let y = stack.pop().unwrap();
+                let x = stack.pop().unwrap();
+                runtime_equality(&env, op, x, y)
+            }
+            Instr::Comparison(op) => {
+                let y = stack.pop().unwrap();
+                let x = stack.pop().unwrap();
+                runtime_comparison(&env, op, x, y)
+            }
+            Instr::Unary(op) => {
+                let x = stack.pop().unwrap();
+                runtime_op1(op, x)
+            }
+            Instr::JumpIfFalsy(to) | Instr::JumpIfTruthy(to) => {
+                let truthy = stack.last().unwrap().is_truthy();
+                if truthy == matches!(chunk.code[pc], Instr::JumpIfTruthy(_)) {
+                    pc = *to;
+                } else {
+                    stack.pop();
+                    pc += 1;
+                }
+                continue;
+            }
+            Instr::Func(i) => {
+                let (parameters, body) = &chunk.funcs[*i];
+                b(RuntimeValue::FuncDef {
+                    parameters: parameters.clone(),
+                    body: BuiltinOrExpr::Code(Arc::clone(body)),
+                    env: Env::new(Some(Arc::clone(&env))),
+                })
+            }
+            Instr::Field { name, weak } => {
+                let from = stack.pop().unwrap();
+                runtime_get_field(Arc::clone(&env), from, &chunk.names[*name], *weak)
+            }
+            Instr::Index { is_expr } => {
+                let key = stack.pop().unwrap();
+                let from = stack.pop().unwrap();
+                runtime_index(*from, &key, *is_expr)
+            }
+            Instr::Call(i) => {
+                let names = &chunk.calls[*i];
+                let values = stack.split_off(stack.len() - names.len());
+                let func = stack.pop().unwrap();
+                let arguments: Vec<Argument> = names.iter().cloned().zip(values.into_iter().map(|v| *v)).collect();
+                call_result(runtime_call(Arc::clone(&env), func, arguments, HashMap::new()))
+            }
+            Instr::Source => {
+                let value = stack.pop().unwrap();
+                match RuntimeIter::new(Arc::clone(&env), *value) {
+                    Ok(source) => sources.push(source),
+                    Err(e) => return b(RuntimeValue::Error(e)),
+                }
+                pc += 1;
+                continue;
+            }
+            Instr::Map => {
+                let func = stack.pop().unwrap();
+                runtime_map(&env, sources.pop().unwrap(), *func)
+            }
+            Instr::Collect => {
+                let func = stack.pop().unwrap();
+                runtime_collect(&env, sources.pop().unwrap(), *func)
+            }
+            Instr::Break => b(RuntimeValue::EOF),
+            Instr::Eval(i) => eval(Arc::clone(&env), vec![chunk.exprs[*i].clone()]),
+        };
+        if let RuntimeValue::EOF | RuntimeValue::Error(_) = *value {
+            return value;
+        }
+        stack.push(value);
+        pc += 1;
+    }
+    stack.pop().unwrap_or_else(|| b(RuntimeValue::None))
+}
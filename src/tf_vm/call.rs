@@ -0,0 +1,77 @@
+use std::sync::{Arc, RwLock};
+use crate::tf_vm::convert::FromRuntime;
+use crate::tf_vm::env::Env;
+use crate::tf_vm::error::{Result, RuntimeError};
+use crate::tf_vm::iter::RuntimeIter;
+use crate::tf_vm::runtimes::{BuiltinOrExpr, RuntimeValue};
+use crate::tf_vm::vm::runtime_func_apply;
+use crate::utils::b;
+
+// a native function, it may hold whatever state the host gives it
+pub type Builtin = Arc<dyn Fn(&CallContext) -> Result<RuntimeValue> + Send + Sync>;
+
+// a call of a native function: its arguments by parameter name, `self` for methods
+pub struct CallContext {
+    env: Arc<RwLock<Env>>,
+}
+
+impl CallContext {
+    pub fn new(env: Arc<RwLock<Env>>) -> CallContext {
+        CallContext { env }
+    }
+
+    // the env the call runs in, for values that outlive the call, like lazy iters
+    pub fn env(&self) -> Arc<RwLock<Env>> {
+        Arc::clone(&self.env)
+    }
+
+    // an argument, if it was given, never a variable of the scope around the call
+    pub fn get(&self, name: &str) -> Option<RuntimeValue> {
+        let env = self.env.read().unwrap();
+        match env.get_own(name) {
+            // a bound method's `self` is in the env it was bound in
+            None if name == "self" => env.get(name.to_string()),
+            value => value,
+        }
+    }
+
+    // an argument as it is, none when it wasn't given
+    pub fn value(&self, name: &str) -> RuntimeValue {
+        self.get(name).unwrap_or(RuntimeValue::None)
+    }
+
+    // an argument as a rust value, a type error names the parameter
+    pub fn arg<T: FromRuntime>(&self, name: &str) -> Result<T> {
+        T::from_runtime(self.value(name)).map_err(|e| match e {
+            RuntimeError::Type(message) => RuntimeError::Type(format!("`{name}`: {message}")),
+            e => e,
+        })
+    }
+
+    // an argument as a stream, from anything iterable
+    pub fn iter(&self, name: &str) -> Result<RuntimeIter> {
+        RuntimeIter::new(self.env(), self.value(name))
+    }
+
+    // calls a function value, a raised error comes back as an `Err`
+    pub fn call(&self, func: &RuntimeValue, values: Vec<RuntimeValue>) -> Result<RuntimeValue> {
+        call(&self.env, func, values)
+    }
+}
+
+pub fn call(env: &Arc<RwLock<Env>>, func: &RuntimeValue, values: Vec<RuntimeValue>) -> Result<RuntimeValue> {
+    match *runtime_func_apply(Arc::clone(env), b(func.clone()), values) {
+        RuntimeValue::Error(e) => Err(e),
+        value => Ok(value)
+    }
+}
+
+// a function value running `body`, with `env` as the scope its arguments are set in
+pub fn native<F>(env: Arc<RwLock<Env>>, parameters: &[&str], body: F) -> RuntimeValue
+    where F: Fn(&CallContext) -> Result<RuntimeValue> + Send + Sync + 'static {
+    RuntimeValue::FuncDef {
+        parameters: parameters.iter().map(|p| b(p.to_string())).collect(),
+        body: BuiltinOrExpr::Builtin(Arc::new(body)),
+        env,
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::tf_vm::env::Env;
+use crate::tf_vm::error::{Result, RuntimeError};
+use crate::tf_vm::runtimes::RuntimeValue;
+use crate::tf_vm::serde_value::{Deserializer, Serializer};
+use crate::utils::b;
+
+// a rust value handed to a program
+pub trait IntoRuntime {
+    fn into_runtime(self) -> RuntimeValue;
+}
+
+// a rust value read out of a runtime value, a mismatch is a type error
+pub trait FromRuntime: Sized {
+    fn from_runtime(value: RuntimeValue) -> Result<Self>;
+}
+
+fn expected<T>(what: &str, value: &RuntimeValue) -> Result<T> {
+    Err(RuntimeError::Type(format!("expected {what}, not {}", value.repr())))
+}
+
+impl FromRuntime for RuntimeValue {
+    fn from_runtime(value: RuntimeValue) -> Result<Self> {
+        Ok(value)
+    }
+}
+
+// anything has a truthiness
+impl FromRuntime for bool {
+    fn from_runtime(value: RuntimeValue) -> Result<Self> {
+        Ok(value.is_truthy())
+    }
+}
+
+// any int type takes the ints that fit it
+macro_rules! from_runtime_int {
+    ($($t:ty => $what:literal),*) => {$(
+        impl FromRuntime for $t {
+            fn from_runtime(value: RuntimeValue) -> Result<Self> {
+                let n = match value {
+                    RuntimeValue::Int64(n) => <$t>::try_from(n).ok(),
+                    RuntimeValue::Int128(n) => <$t>::try_from(n).ok(),
+                    _ => None
+                };
+                n.map_or_else(|| expected($what, &value), Ok)
+            }
+        }
+    )*};
+}
+
+from_runtime_int!(
+    i32 => "an i32", i64 => "an i64", i128 => "an int", u8 => "a u8", u32 => "a u32", u64 => "a u64",
+    usize => "a non-negative int"
+);
+
+impl FromRuntime for f64 {
+    fn from_runtime(value: RuntimeValue) -> Result<Self> {
+        match value {
+            RuntimeValue::Float(x) => Ok(x),
+            RuntimeValue::Int64(n) => Ok(n as f64),
+            RuntimeValue::Int128(n) => Ok(n as f64),
+            value => expected("a float", &value),
+        }
+    }
+}
+
+impl FromRuntime for String {
+    fn from_runtime(value: RuntimeValue) -> Result<Self> {
+        match value {
+            RuntimeValue::String(s) => Ok(*s),
+            value => expected("a str", &value),
+        }
+    }
+}
+
+// none, or a missing argument, is `None`
+impl<T: FromRuntime> FromRuntime for Option<T> {
+    fn from_runtime(value: RuntimeValue) -> Result<Self> {
+        match value {
+            RuntimeValue::None => Ok(None),
+            value => T::from_runtime(value).map(Some),
+        }
+    }
+}
+
+impl<T: FromRuntime> FromRuntime for Vec<T> {
+    fn from_runtime(value: RuntimeValue) -> Result<Self> {
+        match value {
+            RuntimeValue::List(list) => list.into_iter().map(|v| T::from_runtime(*v)).collect(),
+            value => expected("a list", &value),
+        }
+    }
+}
+
+// an object's own fields, not the ones it inherits
+impl<T: FromRuntime> FromRuntime for HashMap<String, T> {
+    fn from_runtime(value: RuntimeValue) -> Result<Self> {
+        match value {
+            RuntimeValue::WithEnv { value, env } if !matches!(value.as_ref(), RuntimeValue::FuncDef { .. }) => {
+                let variables = env.read().unwrap().variables();
+                variables.into_iter().map(|(k, v)| T::from_runtime(v).map(|v| (k, v))).collect()
+            }
+            value => expected("an object", &value),
+        }
+    }
+}
+
+impl IntoRuntime for RuntimeValue {
+    fn into_runtime(self) -> RuntimeValue {
+        self
+    }
+}
+
+impl IntoRuntime for () {
+    fn into_runtime(self) -> RuntimeValue {
+        RuntimeValue::None
+    }
+}
+
+impl IntoRuntime for bool {
+    fn into_runtime(self) -> RuntimeValue {
+        RuntimeValue::Bool(self)
+    }
+}
+
+// ints become an i64 when they fit, an i128 when they don't
+macro_rules! into_runtime_int {
+    ($($t:ty),*) => {$(
+        impl IntoRuntime for $t {
+            fn into_runtime(self) -> RuntimeValue {
+                match i64::try_from(self) {
+                    Ok(n) => RuntimeValue::Int64(n),
+                    Err(_) => RuntimeValue::Int128(self as i128),
+                }
+            }
+        }
+    )*};
+}
+
+into_runtime_int!(i32, i64, i128, u8, u32, u64, usize);
+
+impl IntoRuntime for f64 {
+    fn into_runtime(self) -> RuntimeValue {
+        RuntimeValue::Float(self)
+    }
+}
+
+impl IntoRuntime for String {
+    fn into_runtime(self) -> RuntimeValue {
+        RuntimeValue::String(b(self))
+    }
+}
+
+impl IntoRuntime for &str {
+    fn into_runtime(self) -> RuntimeValue {
+        RuntimeValue::String(b(self.to_string()))
+    }
+}
+
+impl<T: IntoRuntime> IntoRuntime for Option<T> {
+    fn into_runtime(self) -> RuntimeValue {
+        self.map_or(RuntimeValue::None, T::into_runtime)
+    }
+}
+
+impl<T: IntoRuntime> IntoRuntime for Vec<T> {
+    fn into_runtime(self) -> RuntimeValue {
+        RuntimeValue::List(self.into_iter().map(|v| b(v.into_runtime())).collect())
+    }
+}
+
+impl<T: IntoRuntime> IntoRuntime for HashMap<String, T> {
+    fn into_runtime(self) -> RuntimeValue {
+        RuntimeValue::WithEnv {
+            env: Env::from(self.into_iter().map(|(k, v)| (k, v.into_runtime())).collect(), None),
+            value: b(RuntimeValue::None),
+        }
+    }
+}
+
+// any `Serialize` value, structs and maps become objects; a value takes the same shape here as it
+// would in `json.parse`, but a float or an int that json can't hold comes through as it is
+pub fn serialize<T: Serialize + ?Sized>(value: &T) -> Result<RuntimeValue> {
+    value.serialize(Serializer)
+}
+
+// the other way, for any value laid out like that
+pub fn deserialize<T: DeserializeOwned>(value: &RuntimeValue) -> Result<T> {
+    T::deserialize(Deserializer::new(value))
+}
@@ -0,0 +1,163 @@
+use std::io::Read;
+use std::sync::{Arc, RwLock};
+use crate::tf_vm::env::Env;
+use crate::tf_vm::error::{Result, RuntimeError};
+use crate::tf_vm::call::{native, CallContext};
+use crate::tf_vm::iter::{to_runtime_iter, RuntimeIter};
+use crate::tf_vm::runtimes::RuntimeValue;
+use crate::utils::b;
+
+// the lines of a stream as bytes, so quoted fields can run over several of them
+struct StreamReader {
+    lines: RuntimeIter,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.line.len() {
+            match self.lines.next() {
+                None => return Ok(0),
+                Some(Ok(line)) => {
+                    self.line = format!("{line}\n").into_bytes();
+                    self.pos = 0;
+                }
+                Some(Err(e)) => return Err(std::io::Error::other(e)),
+            }
+        }
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+// an error from the stream itself comes back as it was
+fn csv_error(source: &str, e: ::csv::Error) -> RuntimeError {
+    match e.kind() {
+        ::csv::ErrorKind::Io(io) => match io.get_ref().and_then(|e| e.downcast_ref::<RuntimeError>()) {
+            Some(e) => e.clone(),
+            None => RuntimeError::Io(format!("can't read '{source}': {io}")),
+        },
+        _ => RuntimeError::Value(format!("bad csv in '{source}': {e}")),
+    }
+}
+
+// with a header, each row is an object keyed by it, without one a list of its fields
+struct Rows {
+    source: String,
+    records: ::csv::StringRecordsIntoIter<Box<dyn Read + Send>>,
+    header: Option<Vec<String>>,
+    has_header: bool,
+}
+
+impl Iterator for Rows {
+    type Item = Result<RuntimeValue>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let record = match self.records.next()? {
+                Ok(record) => record,
+                Err(e) => return Some(Err(csv_error(&self.source, e))),
+            };
+            let fields = record.iter().map(|field| RuntimeValue::String(b(field.to_string())));
+            match &self.header {
+                None if self.has_header => self.header = Some(record.iter().map(String::from).collect()),
+                None => return Some(Ok(RuntimeValue::List(fields.map(b).collect()))),
+                // a short row leaves its last columns empty
+                Some(header) => {
+                    let fields = fields.chain(std::iter::repeat(RuntimeValue::String(b(String::new()))));
+                    return Some(Ok(RuntimeValue::WithEnv {
+                        env: Env::from(header.iter().cloned().zip(fields).collect(), None),
+                        value: b(RuntimeValue::None),
+                    }));
+                }
+            }
+        }
+    }
+}
+
+// one byte, with `\t` for a tab
+fn sep_arg(ctx: &CallContext) -> Result<u8> {
+    match ctx.value("sep") {
+        RuntimeValue::None => Ok(b','),
+        RuntimeValue::String(s) if s.as_str() == "\\t" => Ok(b'\t'),
+        RuntimeValue::String(s) if s.len() == 1 => Ok(s.as_bytes()[0]),
+        value => Err(RuntimeError::Value(format!("`sep` must be a single character, not {}", value.repr()))),
+    }
+}
+
+fn read_rows(ctx: &CallContext) -> Result<RuntimeValue> {
+    let sep = sep_arg(ctx)?;
+    let (source, reader): (String, Box<dyn Read + Send>) = match ctx.value("source") {
+        RuntimeValue::String(path) => {
+            let file = std::fs::File::open(path.as_str())
+                .map_err(|e| RuntimeError::Io(format!("can't open '{path}': {e}")))?;
+            (*path, Box::new(file))
+        }
+        stream => {
+            let lines = RuntimeIter::new(ctx.env(), stream)?;
+            ("stream".to_string(), Box::new(StreamReader { lines, line: vec![], pos: 0 }))
+        }
+    };
+    let records = ::csv::ReaderBuilder::new().delimiter(sep).has_headers(false).flexible(true)
+        .from_reader(reader).into_records();
+    let has_header = ctx.arg::<Option<bool>>("header")?.unwrap_or(true);
+    Ok(to_runtime_iter(Rows { source, records, header: None, has_header }))
+}
+
+fn columns_arg(ctx: &CallContext) -> Result<Option<Vec<String>>> {
+    match ctx.value("columns") {
+        RuntimeValue::None => Ok(None),
+        RuntimeValue::List(columns) => Ok(Some(columns.iter().map(|c| c.to_string()).collect())),
+        value => Err(RuntimeError::Type(format!("`columns` must be a list, not {}", value.repr()))),
+    }
+}
+
+fn field(value: &RuntimeValue) -> String {
+    match value {
+        RuntimeValue::None => String::new(),
+        value => value.to_string(),
+    }
+}
+
+// a row of fields as one line, quoted where needed, without its line break
+fn csv_line(sep: u8, fields: impl IntoIterator<Item = String>) -> Result<RuntimeValue> {
+    let mut writer = ::csv::WriterBuilder::new().delimiter(sep).from_writer(vec![]);
+    writer.write_record(fields.into_iter().collect::<Vec<_>>())
+        .map_err(|e| RuntimeError::Value(e.to_string()))?;
+    let mut line = String::from_utf8(writer.into_inner().map_err(|e| RuntimeError::Value(e.to_string()))?).unwrap();
+    line.truncate(line.trim_end_matches(['\r', '\n']).len());
+    Ok(RuntimeValue::String(b(line)))
+}
+
+// objects give their own fields named in `columns`, lists give theirs in order
+fn row_fields(row: &RuntimeValue, columns: &Option<Vec<String>>) -> Result<Vec<String>> {
+    match (row, columns) {
+        (RuntimeValue::List(fields), _) => Ok(fields.iter().map(|f| field(f)).collect()),
+        (RuntimeValue::WithEnv { value: _, env }, Some(columns)) => {
+            let env = env.read().unwrap();
+            Ok(columns.iter().map(|c| env.get_own(c).map_or(String::new(), |f| field(&f))).collect())
+        }
+        (RuntimeValue::WithEnv { value: _, env: _ }, None) => Err(RuntimeError::Value("objects need `columns` to be written as csv".to_string())),
+        (row, _) => Err(RuntimeError::Type(format!("a csv row must be an object or a list, not {}", row.repr()))),
+    }
+}
+
+fn write_rows(ctx: &CallContext) -> Result<RuntimeValue> {
+    let sep = sep_arg(ctx)?;
+    let columns = columns_arg(ctx)?;
+    let rows = ctx.iter("rows")?;
+    let has_header = ctx.arg::<Option<bool>>("header")?.unwrap_or(true);
+    let header = columns.clone().filter(|_| has_header).map(|columns| csv_line(sep, columns));
+    Ok(to_runtime_iter(header.into_iter().chain(rows.map(move |row| csv_line(sep, row_fields(&row?, &columns)?)))))
+}
+
+// `csv` reads a file or a stream of lines a row at a time, `csv_lines` turns rows back into lines
+pub fn init_csv(env: Arc<RwLock<Env>>) -> Vec<(String, RuntimeValue)> {
+    vec![
+        ("csv".to_string(), native(env.clone(), &["source", "sep", "header"], read_rows)),
+        ("csv_lines".to_string(), native(env, &["rows", "columns", "sep", "header"], write_rows)),
+    ]
+}
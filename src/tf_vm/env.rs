@@ -1,48 +1,91 @@
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, RwLock, Weak};
 use crate::tf_vm::runtimes::RuntimeValue;
 
 pub struct Env {
     parent: Option<Arc<RwLock<Env>>>,
     variables: HashMap<String, RuntimeValue>,
-    arc_lock_self: Option<Arc<RwLock<Env>>>,
+    // weak, or every env would keep itself alive
+    arc_lock_self: Weak<RwLock<Env>>,
+    // a record env only owns the record's variables, other assignments go to the parent
+    pass_through: bool,
 }
 
 impl Env {
     pub fn new(parent: Option<Arc<RwLock<Env>>>) -> Arc<RwLock<Env>> {
-        let arc_lock_self = Arc::new(RwLock::new(Env {
-            parent,
-            variables: HashMap::new(),
-            arc_lock_self: None,
-        }));
-        arc_lock_self.write().unwrap().arc_lock_self = Some(arc_lock_self.clone());
-        arc_lock_self
+        Env::from(HashMap::new(), parent)
     }
 
     pub fn from(variables: HashMap<String, RuntimeValue>, parent: Option<Arc<RwLock<Env>>>) -> Arc<RwLock<Env>> {
-        let arc_lock_self = Arc::new(RwLock::new(Env {
+        Arc::new_cyclic(|arc_lock_self| RwLock::new(Env {
             parent,
             variables,
-            arc_lock_self: None,
-        }));
-        arc_lock_self.write().unwrap().arc_lock_self = Some(arc_lock_self.clone());
-        arc_lock_self
+            arc_lock_self: arc_lock_self.clone(),
+            pass_through: false,
+        }))
+    }
+
+    pub fn pass_through(variables: HashMap<String, RuntimeValue>, parent: Arc<RwLock<Env>>) -> Arc<RwLock<Env>> {
+        let env = Env::from(variables, Some(parent));
+        env.write().unwrap().pass_through = true;
+        env
     }
 
     pub fn update_variables(&mut self, variables: HashMap<String, RuntimeValue>) {
         self.variables = variables;
     }
 
+    pub fn variables(&self) -> HashMap<String, RuntimeValue> {
+        self.variables.clone()
+    }
+
+    // the same without copying them, for what only looks
+    pub fn own_variables(&self) -> &HashMap<String, RuntimeValue> {
+        &self.variables
+    }
+
+    // every name visible from here, own and inherited
+    pub fn names(&self) -> BTreeSet<String> {
+        let mut names = self.parent.as_ref().map(|parent| parent.read().unwrap().names()).unwrap_or_default();
+        names.extend(self.variables.keys().cloned());
+        names
+    }
+
     pub fn empty() -> Arc<RwLock<Env>> {
         Env::new(None)
     }
 
     pub fn get(&self, key: String) -> Option<RuntimeValue> {
-        self.variables.get(key.as_str()).map(|i| (*i).clone()).
-            or_else(|| self.parent.as_ref().map(|env| env.read().unwrap().get(key)).unwrap_or(None))
+        self.lookup(&key)
+    }
+
+    pub fn lookup(&self, key: &str) -> Option<RuntimeValue> {
+        self.variables.get(key).cloned().
+            or_else(|| self.parent.as_ref().and_then(|env| env.read().unwrap().lookup(key)))
+    }
+
+    // only this env's own variables, not its parents'
+    pub fn get_own(&self, key: &str) -> Option<RuntimeValue> {
+        self.variables.get(key).cloned()
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<RuntimeValue> {
+        self.variables.remove(key)
     }
 
     pub fn set(&mut self, key: String, value: RuntimeValue) {
-        self.variables.insert(key, value);
+        match &self.parent {
+            Some(parent) if self.pass_through && !self.variables.contains_key(&key) => parent.write().unwrap().set(key, value),
+            _ => {
+                self.variables.insert(key, value);
+            }
+        }
+    }
+
+    pub fn root(&self) -> Arc<RwLock<Env>> {
+        match &self.parent {
+            Some(parent) => parent.read().unwrap().root(),
+            None => self.arc_lock_self.upgrade().unwrap(),
+        }
     }
 }
@@ -0,0 +1,42 @@
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone)]
+pub enum RuntimeError {
+    // a value doesn't support what is asked of it, e.g. iterating a number
+    Type(String),
+    // reading or writing outside the program failed
+    Io(String),
+    // an argument has the right type but can't be used, e.g. a malformed glob pattern
+    Value(String),
+    // a name that isn't defined, or a field an object doesn't have
+    Name(String),
+}
+
+impl RuntimeError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RuntimeError::Type(_) => "type",
+            RuntimeError::Io(_) => "io",
+            RuntimeError::Value(_) => "value",
+            RuntimeError::Name(_) => "name",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            RuntimeError::Type(message) | RuntimeError::Io(message) | RuntimeError::Value(message) | RuntimeError::Name(message) => {
+                message.clone()
+            }
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, RuntimeError>;
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} error: {}", self.kind(), self.message())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
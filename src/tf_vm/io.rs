@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Stdin, Write};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, RwLock};
+use std::time::UNIX_EPOCH;
+use regex::Regex;
+use crate::tf_vm::env::Env;
+use crate::tf_vm::error::{Result, RuntimeError};
+use crate::tf_vm::call::{native, CallContext};
+use crate::tf_vm::iter::to_runtime_iter;
+use crate::tf_vm::limits;
+use crate::tf_vm::records::{Continued, RecordSeparator, Records};
+use crate::tf_vm::runtimes::RuntimeValue;
+use crate::utils::b;
+
+// stdin behind one buffer, built on first use, so the `stdin` builtin, the input records
+// and `-` in the files never buffer lines away from each other
+static STDIN: OnceLock<Mutex<BufReader<Stdin>>> = OnceLock::new();
+
+// a reader on the shared stdin, it takes a whole line from it at a time
+pub struct SharedStdin {
+    line: Vec<u8>,
+    pos: usize,
+}
+
+pub fn stdin() -> SharedStdin {
+    SharedStdin { line: vec![], pos: 0 }
+}
+
+impl Read for SharedStdin {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = {
+            let available = self.fill_buf()?;
+            let n = available.len().min(buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+// how long a read waits on stdin between checks for a cancel or a timeout
+#[cfg(unix)]
+const POLL_MS: u16 = 50;
+
+// returns once stdin can be read without blocking
+#[cfg(unix)]
+fn wait_for_input() {
+    use std::os::fd::AsFd;
+    use nix::poll::{poll, PollFd, PollFlags};
+    let stdin = std::io::stdin();
+    loop {
+        let mut fds = [PollFd::new(stdin.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, POLL_MS) {
+            Ok(0) | Err(nix::errno::Errno::EINTR) => limits::wait(),
+            // readable, at its end, or an error the read will report
+            _ => return,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn wait_for_input() {}
+
+impl BufRead for SharedStdin {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.pos == self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            let shared = STDIN.get_or_init(|| Mutex::new(BufReader::new(std::io::stdin())));
+            // a cancel unwinds out of the wait, before anything is taken from the buffer
+            let mut shared = shared.lock().unwrap_or_else(PoisonError::into_inner);
+            // only what's there is read at a time, so a line that's slow to come in can be waited on
+            loop {
+                if shared.buffer().is_empty() {
+                    wait_for_input();
+                }
+                let available = match shared.fill_buf() {
+                    Ok(available) => available,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                let (n, done) = match available.iter().position(|c| *c == b'\n') {
+                    Some(i) => (i + 1, true),
+                    None => (available.len(), available.is_empty()),
+                };
+                self.line.extend_from_slice(&available[..n]);
+                shared.consume(n);
+                if done {
+                    break;
+                }
+            }
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.line.len());
+    }
+}
+
+// yields one record at a time, without its separator, so input of any size streams through
+pub fn read_records<R: BufRead + Send + 'static>(reader: R, separator: RecordSeparator) -> RuntimeValue {
+    to_runtime_iter(Records::new(reader, separator).map(|record| match record {
+        Ok(record) => Ok(RuntimeValue::String(b(record))),
+        Err(e) => Err(RuntimeError::Io(e.to_string())),
+    }))
+}
+
+pub fn read_lines<R: BufRead + Send + 'static>(reader: R) -> RuntimeValue {
+    read_records(reader, RecordSeparator::Newline)
+}
+
+// the records of each file in turn
+pub fn input_records(files: Vec<String>, separator: RecordSeparator) -> RuntimeValue {
+    to_runtime_iter(files.into_iter().flat_map(move |file| -> Box<dyn Iterator<Item = Result<RuntimeValue>> + Send> {
+        match std::fs::File::open(&file) {
+            Ok(reader) => Box::new(Records::new(BufReader::new(reader), separator.clone()).map(move |record| match record {
+                Ok(record) => Ok(RuntimeValue::String(b(record))),
+                Err(e) => Err(io_error("read", &file, e)),
+            })),
+            Err(e) => Box::new(std::iter::once(Err(io_error("open", &file, e)))),
+        }
+    }))
+}
+
+fn io_error(action: &str, path: &str, e: impl std::fmt::Display) -> RuntimeError {
+    RuntimeError::Io(format!("can't {action} '{path}': {e}"))
+}
+
+// by default a line that doesn't start with a blank begins a record, as in stack traces
+fn start_arg(ctx: &CallContext) -> Result<Regex> {
+    match ctx.arg::<Option<RuntimeValue>>("start")? {
+        None => Ok(Regex::new(r"^\S").unwrap()),
+        Some(RuntimeValue::Regex(pattern)) => Regex::new(&pattern)
+            .map_err(|e| RuntimeError::Value(format!("bad start pattern /{pattern}/: {e}"))),
+        Some(value) => Err(RuntimeError::Type(format!("`start` must be a regex, not {}", value.repr()))),
+    }
+}
+
+fn path_object(entry: &walkdir::DirEntry) -> Result<RuntimeValue> {
+    let path = entry.path().to_string_lossy().to_string();
+    let metadata = entry.metadata().map_err(|e| io_error("stat", &path, e))?;
+    let mtime = metadata.modified().ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as i64);
+    Ok(RuntimeValue::WithEnv {
+        env: Env::from(HashMap::from([
+            ("name".to_string(), RuntimeValue::String(b(entry.file_name().to_string_lossy().to_string()))),
+            ("path".to_string(), RuntimeValue::String(b(path))),
+            ("size".to_string(), RuntimeValue::Int64(metadata.len() as i64)),
+            ("mtime".to_string(), RuntimeValue::Int64(mtime)),
+            ("is_dir".to_string(), RuntimeValue::Bool(metadata.is_dir())),
+        ]), None),
+        value: b(RuntimeValue::None),
+    })
+}
+
+pub fn init_io(env: Arc<RwLock<Env>>) -> Vec<(String, RuntimeValue)> {
+    vec![
+        ("stdin".to_string(), read_lines(stdin())),
+        ("lines".to_string(), native(env.clone(), &["file"], |ctx| {
+            let file: String = ctx.arg("file")?;
+            let reader = std::fs::File::open(&file).map_err(|e| io_error("open", &file, e))?;
+            Ok(read_lines(BufReader::new(reader)))
+        })),
+        // joins continuation lines onto the line that starts their record
+        ("records".to_string(), native(env.clone(), &["stream", "start"], |ctx| {
+            let start = start_arg(ctx)?;
+            Ok(to_runtime_iter(Continued::new(ctx.iter("stream")?, start)))
+        })),
+        ("print".to_string(), native(env.clone(), &["value"], |ctx| {
+            println!("{}", ctx.value("value"));
+            Ok(RuntimeValue::None)
+        })),
+        ("read".to_string(), native(env.clone(), &["path"], |ctx| {
+            let path: String = ctx.arg("path")?;
+            std::fs::read_to_string(&path)
+                .map(|s| RuntimeValue::String(b(s)))
+                .map_err(|e| io_error("read", &path, e))
+        })),
+        ("write".to_string(), native(env.clone(), &["path", "s"], |ctx| {
+            let path: String = ctx.arg("path")?;
+            std::fs::write(&path, ctx.value("s").to_string())
+                .map(|_| RuntimeValue::None)
+                .map_err(|e| io_error("write", &path, e))
+        })),
+        ("append".to_string(), native(env.clone(), &["path", "s"], |ctx| {
+            let path: String = ctx.arg("path")?;
+            std::fs::OpenOptions::new().create(true).append(true).open(&path)
+                .and_then(|mut file| file.write_all(ctx.value("s").to_string().as_bytes()))
+                .map(|_| RuntimeValue::None)
+                .map_err(|e| io_error("append to", &path, e))
+        })),
+        ("exists".to_string(), native(env.clone(), &["path"], |ctx| {
+            Ok(RuntimeValue::Bool(std::path::Path::new(&ctx.arg::<String>("path")?).exists()))
+        })),
+        ("glob".to_string(), native(env.clone(), &["pattern"], |ctx| {
+            let pattern: String = ctx.arg("pattern")?;
+            let paths = glob::glob(&pattern)
+                .map_err(|e| RuntimeError::Value(format!("bad glob pattern '{pattern}': {e}")))?;
+            Ok(to_runtime_iter(paths.map(|path| match path {
+                Ok(path) => Ok(RuntimeValue::String(b(path.to_string_lossy().to_string()))),
+                Err(e) => Err(io_error("read", &e.path().to_string_lossy(), e.error())),
+            })))
+        })),
+        // everything below `dir`, depth first, as {name, path, size, mtime, is_dir}
+        ("walk".to_string(), native(env.clone(), &["dir"], |ctx| {
+            let dir: String = ctx.arg("dir")?;
+            Ok(to_runtime_iter(walkdir::WalkDir::new(&dir).min_depth(1).into_iter().map(move |entry| match entry {
+                Ok(entry) => path_object(&entry),
+                Err(e) => Err(io_error("walk", &dir, e)),
+            })))
+        })),
+    ]
+}
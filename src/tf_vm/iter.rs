@@ -0,0 +1,298 @@
+use std::collections::{HashMap, VecDeque};
+use std::iter::Peekable;
+use std::sync::{Arc, Mutex, RwLock, TryLockError};
+use crate::ast::Op;
+use crate::tf_vm::env::Env;
+use crate::tf_vm::error::{Result, RuntimeError};
+use crate::tf_vm::limits;
+use crate::tf_vm::call::{call, native, CallContext};
+use crate::tf_vm::runtimes::RuntimeValue;
+use crate::tf_vm::vm::{builtin_op2, runtime_cmp, runtime_func_call, runtime_get_field, runtime_operator};
+use crate::utils::b;
+
+pub type BoxedIter = Box<dyn Iterator<Item = Result<RuntimeValue>> + Send>;
+
+// every native iter can peek, so `peekable` costs nothing
+pub type NativeIter = Arc<Mutex<Peekable<BoxedIter>>>;
+
+// wraps a rust iterator into an `iter` value
+pub fn to_runtime_iter<I>(iter: I) -> RuntimeValue
+    where I: Iterator<Item = Result<RuntimeValue>> + Send + 'static {
+    let iter: BoxedIter = Box::new(iter);
+    RuntimeValue::Iter(Arc::new(Mutex::new(iter.peekable())))
+}
+
+// an iter pulled again from inside its own pipeline would deadlock, so that is an error instead
+// an evaluation cut short while pulling from an iter leaves it poisoned, it is still fine to go on with
+fn lock_native(iter: &NativeIter) -> Result<std::sync::MutexGuard<'_, Peekable<BoxedIter>>> {
+    match iter.try_lock() {
+        Ok(iter) => Ok(iter),
+        Err(TryLockError::Poisoned(e)) => Ok(e.into_inner()),
+        Err(TryLockError::WouldBlock) => Err(RuntimeError::Type("iter is already being iterated".to_string())),
+    }
+}
+
+pub fn next_native(iter: &NativeIter) -> Option<Result<RuntimeValue>> {
+    limits::tick();
+    match lock_native(iter) {
+        Ok(mut iter) => iter.next(),
+        Err(e) => Some(Err(e)),
+    }
+}
+
+pub fn peek_native(iter: &NativeIter) -> Option<Result<RuntimeValue>> {
+    match lock_native(iter) {
+        Ok(mut iter) => iter.peek().cloned(),
+        Err(e) => Some(Err(e)),
+    }
+}
+
+// an object's env chain ends in the globals, so only functions count as methods
+fn get_method(env: Arc<RwLock<Env>>, value: &RuntimeValue, name: &str) -> Option<Box<RuntimeValue>> {
+    let method = runtime_get_field(env, b(value.clone()), name, true);
+    match method.as_ref() {
+        RuntimeValue::FuncDef { parameters: _, body: _, env: _ } => Some(method),
+        RuntimeValue::WithEnv { value, env: _ } if matches!(value.as_ref(), RuntimeValue::FuncDef { parameters: _, body: _, env: _ }) => Some(method),
+        _ => None
+    }
+}
+
+// a text-flow iterator seen from rust: `iter` is called once, then `next` until it returns EOF
+pub enum RuntimeIter {
+    Native(NativeIter),
+    Protocol {
+        env: Arc<RwLock<Env>>,
+        next: Box<RuntimeValue>,
+    },
+    Done,
+}
+
+impl RuntimeIter {
+    pub fn new(env: Arc<RwLock<Env>>, value: RuntimeValue) -> Result<RuntimeIter> {
+        let iter = match &value {
+            RuntimeValue::Iter(iter) => return Ok(RuntimeIter::Native(iter.clone())),
+            RuntimeValue::EOF => return Err(RuntimeError::Type("EOF is not iterable".to_string())),
+            _ => match get_method(Arc::clone(&env), &value, "iter") {
+                Some(iter) => *runtime_func_call(Arc::clone(&env), iter, vec![], HashMap::new()),
+                // something with a `next` already is an iterator
+                None => value,
+            }
+        };
+        if let RuntimeValue::Iter(iter) = iter {
+            return Ok(RuntimeIter::Native(iter));
+        }
+        match get_method(Arc::clone(&env), &iter, "next") {
+            Some(next) => Ok(RuntimeIter::Protocol { env, next }),
+            None => Err(RuntimeError::Type(format!("{} is not iterable", iter.repr())))
+        }
+    }
+}
+
+impl Iterator for RuntimeIter {
+    type Item = Result<RuntimeValue>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let value = match self {
+            RuntimeIter::Native(iter) => next_native(iter),
+            RuntimeIter::Protocol { env, next } => {
+                match *runtime_func_call(Arc::clone(env), next.clone(), vec![], HashMap::new()) {
+                    RuntimeValue::EOF => None,
+                    RuntimeValue::Error(e) => Some(Err(e)),
+                    value => Some(Ok(value)),
+                }
+            }
+            RuntimeIter::Done => None,
+        };
+        if value.is_none() {
+            *self = RuntimeIter::Done;
+        }
+        value
+    }
+}
+
+// `self` is whatever a method was called on, or given as `self=`, so it may be no iter at all
+fn self_iter(ctx: &CallContext) -> Result<RuntimeIter> {
+    ctx.iter("self")
+}
+
+// a count of elements, where zero would never make progress
+fn positive_arg(ctx: &CallContext, name: &str) -> Result<usize> {
+    match ctx.arg(name)? {
+        0 => Err(RuntimeError::Value(format!("`{name}` must be positive"))),
+        n => Ok(n)
+    }
+}
+
+pub fn to_value(value: Result<RuntimeValue>) -> RuntimeValue {
+    value.unwrap_or_else(RuntimeValue::Error)
+}
+
+// `f` is optional for any/all, the element's own truthiness is used without it
+fn test(env: &Arc<RwLock<Env>>, func: &RuntimeValue, value: RuntimeValue) -> Result<bool> {
+    match func {
+        RuntimeValue::None => Ok(value.is_truthy()),
+        func => call(env, func, vec![value]).map(|v| v.is_truthy())
+    }
+}
+
+// the element if the predicate holds for it, or `None`; errors, the source's or the predicate's, reach the consumer
+fn keep(env: &Arc<RwLock<Env>>, func: &RuntimeValue, value: Result<RuntimeValue>) -> Option<Result<RuntimeValue>> {
+    value.and_then(|value| Ok(holds(env, func, &value)?.then_some(value))).transpose()
+}
+
+fn holds(env: &Arc<RwLock<Env>>, func: &RuntimeValue, value: &RuntimeValue) -> Result<bool> {
+    call(env, func, vec![value.clone()]).map(|v| v.is_truthy())
+}
+
+fn add(env: &Arc<RwLock<Env>>, x: RuntimeValue, y: RuntimeValue) -> Result<RuntimeValue> {
+    match builtin_op2(&Op::Add, &x, &y) {
+        Some(value) => Ok(value),
+        None => match runtime_operator(Arc::clone(env), &Op::Add, b(x.clone()), y.clone()).map(|v| *v) {
+            Some(RuntimeValue::Error(e)) => Err(e),
+            Some(value) => Ok(value),
+            None => Err(RuntimeError::Type(format!("can't add {} and {}", x.repr(), y.repr())))
+        }
+    }
+}
+
+// any/all stop at the first element the predicate gives `stop_at` for
+fn short_circuit_test(ctx: &CallContext, stop_at: bool) -> Result<RuntimeValue> {
+    let func = ctx.value("f");
+    for value in self_iter(ctx)? {
+        if value.and_then(|value| test(&ctx.env(), &func, value))? == stop_at {
+            return Ok(RuntimeValue::Bool(stop_at));
+        }
+    }
+    Ok(RuntimeValue::Bool(!stop_at))
+}
+
+fn pair(x: RuntimeValue, y: RuntimeValue) -> RuntimeValue {
+    RuntimeValue::List(vec![b(x), b(y)])
+}
+
+fn extreme(ctx: &CallContext, keep: std::cmp::Ordering) -> Result<RuntimeValue> {
+    self_iter(ctx)?.try_fold(RuntimeValue::None, |x, y| {
+        let y = y?;
+        match (&x, runtime_cmp(&y, &x)) {
+            (RuntimeValue::None, _) => Ok(y),
+            (_, Some(ordering)) if ordering == keep => Ok(y),
+            (_, Some(_)) => Ok(x),
+            (_, None) => Err(RuntimeError::Type(format!("can't compare {} and {}", x.repr(), y.repr())))
+        }
+    })
+}
+
+fn method<F>(env: &Arc<RwLock<Env>>, name: &str, parameters: &[&str], body: F) -> (String, RuntimeValue)
+    where F: Fn(&CallContext) -> Result<RuntimeValue> + Send + Sync + 'static {
+    let parameters: Vec<&str> = ["self"].iter().chain(parameters).copied().collect();
+    (name.to_string(), native(env.clone(), &parameters, body))
+}
+
+pub fn iter_methods(env: Arc<RwLock<Env>>) -> Vec<(String, RuntimeValue)> {
+    vec![
+        method(&env, "iter", &[], |ctx| Ok(ctx.value("self"))),
+        method(&env, "next", &[], |ctx| match ctx.value("self") {
+            RuntimeValue::Iter(iter) => next_native(&iter).unwrap_or(Ok(RuntimeValue::EOF)),
+            self_value => Err(RuntimeError::Type(format!("only an iter has next, not {}", self_value.repr())))
+        }),
+        method(&env, "peek", &[], |ctx| match ctx.value("self") {
+            RuntimeValue::Iter(iter) => peek_native(&iter).unwrap_or(Ok(RuntimeValue::EOF)),
+            self_value => Err(RuntimeError::Type(format!("only an iter can peek, not {}", self_value.repr())))
+        }),
+        method(&env, "peekable", &[], |ctx| Ok(match ctx.value("self") {
+            iter @ RuntimeValue::Iter(_) => iter,
+            _ => to_runtime_iter(self_iter(ctx)?)
+        })),
+        // lazy
+        method(&env, "filter", &["f"], |ctx| {
+            let (env, func) = (ctx.env(), ctx.value("f"));
+            Ok(to_runtime_iter(self_iter(ctx)?.filter_map(move |v| keep(&env, &func, v))))
+        }),
+        method(&env, "take", &["n"], |ctx| Ok(to_runtime_iter(self_iter(ctx)?.take(ctx.arg("n")?)))),
+        method(&env, "skip", &["n"], |ctx| Ok(to_runtime_iter(self_iter(ctx)?.skip(ctx.arg("n")?)))),
+        method(&env, "step_by", &["n"], |ctx| Ok(to_runtime_iter(self_iter(ctx)?.step_by(positive_arg(ctx, "n")?)))),
+        method(&env, "take_while", &["f"], |ctx| {
+            let (env, func) = (ctx.env(), ctx.value("f"));
+            Ok(to_runtime_iter(self_iter(ctx)?.map_while(move |v| keep(&env, &func, v))))
+        }),
+        method(&env, "zip", &["other"], |ctx| {
+            let other = ctx.iter("other")?;
+            Ok(to_runtime_iter(self_iter(ctx)?.zip(other).map(|(x, y)| Ok(pair(x?, y?)))))
+        }),
+        method(&env, "enumerate", &[], |ctx| {
+            Ok(to_runtime_iter(self_iter(ctx)?.enumerate().map(|(i, v)| Ok(pair(RuntimeValue::Int64(i as i64), v?)))))
+        }),
+        method(&env, "chain", &["other"], |ctx| {
+            let other = ctx.iter("other")?;
+            Ok(to_runtime_iter(self_iter(ctx)?.chain(other)))
+        }),
+        method(&env, "flat_map", &["f"], |ctx| {
+            let (env, func) = (ctx.env(), ctx.value("f"));
+            Ok(to_runtime_iter(self_iter(ctx)?.flat_map(move |v| -> BoxedIter {
+                match v.and_then(|v| call(&env, &func, vec![v])).and_then(|v| RuntimeIter::new(Arc::clone(&env), v)) {
+                    Ok(iter) => Box::new(iter),
+                    Err(e) => Box::new(std::iter::once(Err(e))),
+                }
+            })))
+        }),
+        method(&env, "chunks", &["n"], |ctx| {
+            let n = positive_arg(ctx, "n")?;
+            let mut iter = self_iter(ctx)?;
+            Ok(to_runtime_iter(std::iter::from_fn(move || {
+                let mut chunk = Vec::with_capacity(n);
+                while chunk.len() < n {
+                    match iter.next() {
+                        Some(Ok(v)) => chunk.push(b(v)),
+                        Some(Err(e)) => return Some(Err(e)),
+                        None => break,
+                    }
+                }
+                (!chunk.is_empty()).then_some(Ok(RuntimeValue::List(chunk)))
+            })))
+        }),
+        method(&env, "windows", &["n"], |ctx| {
+            let n = positive_arg(ctx, "n")?;
+            let mut iter = self_iter(ctx)?;
+            let mut window = VecDeque::with_capacity(n + 1);
+            Ok(to_runtime_iter(std::iter::from_fn(move || loop {
+                match iter.next()? {
+                    Ok(v) => {
+                        window.push_back(b(v));
+                        if window.len() > n {
+                            window.pop_front();
+                        }
+                        if window.len() == n {
+                            return Some(Ok(RuntimeValue::List(window.iter().cloned().collect())));
+                        }
+                    }
+                    Err(e) => return Some(Err(e)),
+                }
+            })))
+        }),
+        // eager, an error anywhere in the stream is raised from here
+        method(&env, "count", &[], |ctx| {
+            self_iter(ctx)?.try_fold(0, |n, v| v.map(|_| n + 1)).map(RuntimeValue::Int64)
+        }),
+        method(&env, "sum", &[], |ctx| {
+            self_iter(ctx)?.try_fold(RuntimeValue::Int64(0), |x, y| add(&ctx.env(), x, y?))
+        }),
+        method(&env, "min", &[], |ctx| extreme(ctx, std::cmp::Ordering::Less)),
+        method(&env, "max", &[], |ctx| extreme(ctx, std::cmp::Ordering::Greater)),
+        method(&env, "any", &["f"], |ctx| short_circuit_test(ctx, true)),
+        method(&env, "all", &["f"], |ctx| short_circuit_test(ctx, false)),
+        method(&env, "first", &[], |ctx| self_iter(ctx)?.next().unwrap_or(Ok(RuntimeValue::None))),
+        method(&env, "last", &[], |ctx| self_iter(ctx)?.try_fold(RuntimeValue::None, |_, v| v)),
+        method(&env, "reduce", &["f", "init"], |ctx| {
+            let func = ctx.value("f");
+            let mut iter = self_iter(ctx)?;
+            let init = match ctx.get("init") {
+                Some(init) => init,
+                None => match iter.next() {
+                    Some(first) => first?,
+                    None => return Ok(RuntimeValue::None),
+                }
+            };
+            iter.try_fold(init, |x, y| ctx.call(&func, vec![x, y?]))
+        }),
+    ]
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use serde::Serialize;
+use serde_json::ser::{PrettyFormatter, Serializer};
+use serde_json::{Map, Number, Value};
+use crate::tf_vm::call::{native, CallContext};
+use crate::tf_vm::env::Env;
+use crate::tf_vm::error::{Result, RuntimeError};
+use crate::tf_vm::iter::to_runtime_iter;
+use crate::tf_vm::runtimes::{get_value_type_name, RuntimeValue};
+use crate::utils::b;
+
+// objects become plain objects, and numbers the smallest of i64, i128 and float that holds them
+pub fn from_json(value: Value) -> RuntimeValue {
+    match value {
+        Value::Null => RuntimeValue::None,
+        Value::Bool(x) => RuntimeValue::Bool(x),
+        Value::Number(n) => match n.as_i64() {
+            Some(n) => RuntimeValue::Int64(n),
+            None => match i128::from_str(&n.to_string()) {
+                Ok(n) => RuntimeValue::Int128(n),
+                Err(_) => RuntimeValue::Float(f64::from_str(&n.to_string()).unwrap_or(f64::NAN)),
+            },
+        },
+        Value::String(s) => RuntimeValue::String(b(s)),
+        Value::Array(list) => RuntimeValue::List(list.into_iter().map(|v| b(from_json(v))).collect()),
+        Value::Object(fields) => RuntimeValue::WithEnv {
+            env: Env::from(fields.into_iter().map(|(k, v)| (k, from_json(v))).collect(), None),
+            value: b(RuntimeValue::None),
+        },
+    }
+}
+
+pub fn to_json(value: &RuntimeValue) -> Result<Value> {
+    to_json_inner(value, &mut vec![])
+}
+
+fn to_json_inner(value: &RuntimeValue, seen: &mut Vec<*const RwLock<Env>>) -> Result<Value> {
+    Ok(match value {
+        RuntimeValue::None => Value::Null,
+        RuntimeValue::Bool(x) => Value::Bool(*x),
+        RuntimeValue::Int64(n) => Value::Number(Number::from(*n)),
+        RuntimeValue::Int128(n) => Value::Number(Number::from_str(&n.to_string()).unwrap()),
+        RuntimeValue::Float(x) => Value::Number(Number::from_f64(*x).ok_or_else(
+            || RuntimeError::Value(format!("{x:?} has no json form"))
+        )?),
+        RuntimeValue::String(s) => Value::String(s.to_string()),
+        RuntimeValue::List(list) => Value::Array(list.iter().map(|v| to_json_inner(v, seen)).collect::<Result<_>>()?),
+        RuntimeValue::WithEnv { value, env } if !matches!(value.as_ref(), RuntimeValue::FuncDef { .. }) => {
+            if seen.contains(&Arc::as_ptr(env)) {
+                return Err(RuntimeError::Value("can't dump an object that contains itself".to_string()));
+            }
+            seen.push(Arc::as_ptr(env));
+            let mut fields = Map::new();
+            for (name, value) in env.read().unwrap().variables() {
+                fields.insert(name, to_json_inner(&value, seen)?);
+            }
+            seen.pop();
+            Value::Object(fields)
+        }
+        value => return Err(RuntimeError::Type(format!("can't dump {} to json", get_value_type_name(value)))),
+    })
+}
+
+// serde puts the position at the end of its message, here it goes first
+fn parse_error(e: serde_json::Error, line: usize) -> RuntimeError {
+    let message = e.to_string();
+    let message = message.rsplit_once(" at line ").map_or(message.as_str(), |(message, _)| message);
+    RuntimeError::Value(format!("bad json at line {line}, column {}: {message}", e.column()))
+}
+
+pub fn parse(s: &str) -> Result<RuntimeValue> {
+    serde_json::from_str(s).map(from_json).map_err(|e| {
+        let line = e.line();
+        parse_error(e, line)
+    })
+}
+
+pub fn dump(value: &RuntimeValue, indent: Option<&str>) -> Result<String> {
+    let value = to_json(value)?;
+    Ok(match indent {
+        None => value.to_string(),
+        Some(indent) => {
+            let mut out = vec![];
+            value.serialize(&mut Serializer::with_formatter(&mut out, PrettyFormatter::with_indent(indent.as_bytes())))
+                .map_err(|e| RuntimeError::Value(e.to_string()))?;
+            String::from_utf8(out).unwrap()
+        }
+    })
+}
+
+// more than this many spaces is surely a mistake, and would take as much memory per level
+const MAX_INDENT: i64 = 64;
+
+// a number of spaces, or the indent itself
+fn indent_arg(ctx: &CallContext) -> Result<Option<String>> {
+    match ctx.value("indent") {
+        RuntimeValue::None => Ok(None),
+        RuntimeValue::Int64(n @ 0..=MAX_INDENT) => Ok(Some(" ".repeat(n as usize))),
+        RuntimeValue::Int64(n) => Err(RuntimeError::Value(format!("`indent` must be from 0 to {MAX_INDENT} spaces, not {n}"))),
+        RuntimeValue::String(s) => Ok(Some(*s)),
+        value => Err(RuntimeError::Type(format!("`indent` must be an i64 or a str, not {}", value.repr()))),
+    }
+}
+
+// `json.parse`, `json.dump`, and their NDJSON forms that go a line at a time
+pub fn init_json(env: Arc<RwLock<Env>>) -> RuntimeValue {
+    RuntimeValue::WithEnv {
+        env: Env::from(HashMap::from([
+            ("parse".to_string(), native(env.clone(), &["s"], |ctx| match ctx.value("s") {
+                RuntimeValue::String(s) => parse(&s),
+                value => Err(RuntimeError::Type(format!("can only parse a str, not {}", value.repr()))),
+            })),
+            ("dump".to_string(), native(env.clone(), &["value", "indent"], |ctx| {
+                let indent = indent_arg(ctx)?;
+                dump(&ctx.value("value"), indent.as_deref()).map(|s| RuntimeValue::String(b(s)))
+            })),
+            // blank lines are skipped, errors tell the line of the stream they're on
+            ("parse_lines".to_string(), native(env.clone(), &["stream"], |ctx| {
+                Ok(to_runtime_iter(ctx.iter("stream")?.enumerate().filter_map(|(i, line)| match line {
+                    Ok(RuntimeValue::String(s)) if s.trim().is_empty() => None,
+                    Ok(RuntimeValue::String(s)) => Some(serde_json::from_str(&s).map(from_json).map_err(|e| parse_error(e, i + 1))),
+                    Ok(value) => Some(Err(RuntimeError::Type(format!("can only parse a str, not {}", value.repr())))),
+                    Err(e) => Some(Err(e)),
+                })))
+            })),
+            ("dump_lines".to_string(), native(env.clone(), &["stream"], |ctx| {
+                Ok(to_runtime_iter(ctx.iter("stream")?.map(|value| dump(&value?, None).map(|s| RuntimeValue::String(b(s))))))
+            })),
+        ]), None),
+        value: b(RuntimeValue::None),
+    }
+}
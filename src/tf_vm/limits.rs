@@ -0,0 +1,255 @@
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use crate::tf_vm::env::Env;
+use crate::tf_vm::runtimes::RuntimeValue;
+
+// what one evaluation may use, a limit left unset is unbounded
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    // expressions evaluated, and elements pulled from native iters
+    pub steps: Option<u64>,
+    // approximate bytes of any one string, list or object the program builds
+    pub value_size: Option<usize>,
+    // approximate bytes of all the strings, lists and objects the program builds, counted as they're
+    // built and never given back, so what it holds live at any one time is under it too
+    pub memory: Option<usize>,
+    // nested function calls
+    pub depth: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+// the limit an evaluation ran into; it unwinds past `try`, a program can't catch it
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitExceeded {
+    Steps(u64),
+    ValueSize(usize),
+    Memory(usize),
+    Depth(usize),
+    Timeout(Duration),
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitExceeded::Steps(n) => write!(f, "step limit of {n} exceeded"),
+            LimitExceeded::ValueSize(n) => write!(f, "value size limit of {n} bytes exceeded"),
+            LimitExceeded::Memory(n) => write!(f, "memory limit of {n} bytes exceeded"),
+            LimitExceeded::Depth(n) => write!(f, "call depth limit of {n} exceeded"),
+            LimitExceeded::Timeout(t) => write!(f, "timeout of {t:?} exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+// stops an evaluation from another thread, at its next expression or element pulled from an iter,
+// or while it waits on stdin; off unix a read from stdin waits for its line without checking
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+
+    // a token stays cancelled, and cancels whatever runs with it, until it's reset
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// the payload an evaluation unwinds with once its token is cancelled
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+struct Budget {
+    limits: Limits,
+    token: CancellationToken,
+    deadline: Option<Instant>,
+    steps: u64,
+    ticks: u64,
+    depth: usize,
+    built: usize,
+}
+
+thread_local! {
+    static BUDGET: RefCell<Option<Budget>> = const { RefCell::new(None) };
+}
+
+// the clock is read only every so many ticks, it costs more than the rest of a check
+const CLOCK_EVERY: u64 = 64;
+
+// unwinds without the panic hook, running into a limit isn't a bug to report
+fn exceed(e: LimitExceeded) -> ! {
+    std::panic::resume_unwind(Box::new(e))
+}
+
+fn check_token(budget: &Budget) {
+    if budget.token.is_cancelled() {
+        std::panic::resume_unwind(Box::new(Cancelled))
+    }
+}
+
+// puts the previous budget back however `f` ends, so the thread is left as it was found
+struct Restore(Option<Budget>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        BUDGET.with(|budget| *budget.borrow_mut() = self.0.take());
+    }
+}
+
+pub fn with_limits<T>(limits: &Limits, token: &CancellationToken, f: impl FnOnce() -> T) -> T {
+    let budget = Budget {
+        limits: limits.clone(),
+        token: token.clone(),
+        deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+        steps: 0,
+        ticks: 0,
+        depth: 0,
+        built: 0,
+    };
+    let _restore = Restore(BUDGET.with(|b| b.borrow_mut().replace(budget)));
+    f()
+}
+
+fn check_clock(budget: &mut Budget) -> Option<LimitExceeded> {
+    budget.ticks += 1;
+    match budget.deadline {
+        Some(deadline) if budget.ticks.is_multiple_of(CLOCK_EVERY) && Instant::now() > deadline => {
+            Some(LimitExceeded::Timeout(budget.limits.timeout.unwrap()))
+        }
+        _ => None,
+    }
+}
+
+// every evaluated expression
+pub fn step() {
+    let exceeded = BUDGET.with(|budget| {
+        let mut budget = budget.borrow_mut();
+        let budget = budget.as_mut()?;
+        check_token(budget);
+        budget.steps += 1;
+        match budget.limits.steps {
+            Some(max) if budget.steps > max => Some(LimitExceeded::Steps(max)),
+            _ => check_clock(budget),
+        }
+    });
+    if let Some(e) = exceeded {
+        exceed(e)
+    }
+}
+
+// every element pulled from a native iter, which may run without evaluating anything, so it costs a step too
+pub fn tick() {
+    step()
+}
+
+// every so often while a read waits on input, which neither evaluates nor pulls anything
+pub fn wait() {
+    let exceeded = BUDGET.with(|budget| {
+        let budget = budget.borrow();
+        let budget = budget.as_ref()?;
+        check_token(budget);
+        match budget.deadline {
+            Some(deadline) if Instant::now() > deadline => Some(LimitExceeded::Timeout(budget.limits.timeout.unwrap())),
+            _ => None,
+        }
+    });
+    if let Some(e) = exceeded {
+        exceed(e)
+    }
+}
+
+pub struct CallGuard;
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        BUDGET.with(|budget| if let Some(budget) = budget.borrow_mut().as_mut() {
+            budget.depth -= 1;
+        });
+    }
+}
+
+// held for the length of a function call
+pub fn enter_call() -> CallGuard {
+    let exceeded = BUDGET.with(|budget| {
+        let mut budget = budget.borrow_mut();
+        let budget = budget.as_mut()?;
+        budget.depth += 1;
+        budget.limits.depth.filter(|max| budget.depth > *max).map(LimitExceeded::Depth)
+    });
+    let guard = CallGuard;
+    if let Some(e) = exceeded {
+        exceed(e)
+    }
+    guard
+}
+
+// strings by their length, lists by their elements, objects by their own fields, anything else by a fixed amount
+pub fn approx_size(value: &RuntimeValue) -> usize {
+    size_of(value, &mut vec![])
+}
+
+// an object reached again, as one that holds itself, is only counted the first time
+fn size_of(value: &RuntimeValue, seen: &mut Vec<*const RwLock<Env>>) -> usize {
+    match value {
+        RuntimeValue::String(s) | RuntimeValue::Regex(s) => s.len() + 24,
+        RuntimeValue::List(list) => list.iter().map(|v| size_of(v, seen) + 8).sum::<usize>() + 24,
+        RuntimeValue::WithEnv { env, value } if !seen.contains(&Arc::as_ptr(env)) => {
+            seen.push(Arc::as_ptr(env));
+            let fields = env.read().unwrap().own_variables().iter().map(|(k, v)| k.len() + size_of(v, seen) + 32).sum::<usize>();
+            fields + size_of(value, seen) + 32
+        }
+        _ => 32,
+    }
+}
+
+// what's checked against, and nothing if neither is set
+fn size_limits() -> Option<(Option<usize>, Option<usize>, usize)> {
+    BUDGET.with(|budget| budget.borrow().as_ref().map(|budget| (budget.limits.value_size, budget.limits.memory, budget.built)))
+        .filter(|(value_size, memory, _)| value_size.is_some() || memory.is_some())
+}
+
+// a value of `bytes` about to be built, before it's counted
+pub fn check_size(bytes: usize) {
+    match size_limits() {
+        Some((Some(max), _, _)) if bytes > max => exceed(LimitExceeded::ValueSize(max)),
+        Some((_, Some(max), built)) if built.saturating_add(bytes) > max => exceed(LimitExceeded::Memory(max)),
+        _ => {}
+    }
+}
+
+// a value of `bytes` that's been built
+pub fn charge(bytes: usize) {
+    check_size(bytes);
+    BUDGET.with(|budget| if let Some(budget) = budget.borrow_mut().as_mut() {
+        budget.built = budget.built.saturating_add(bytes);
+    });
+}
+
+// sizing a value walks it, so that only happens under a size limit
+pub fn check_value(value: &RuntimeValue) {
+    if let (Some(_), RuntimeValue::String(_) | RuntimeValue::List(_) | RuntimeValue::WithEnv { env: _, value: _ }) = (size_limits(), value) {
+        charge(approx_size(value))
+    }
+}
@@ -1,6 +1,18 @@
 pub mod vm;
+pub mod bytecode;
 pub mod env;
 pub mod builtins;
+pub mod call;
+pub mod convert;
+pub mod serde_value;
+pub mod modules;
+pub mod iter;
+pub mod limits;
+pub mod io;
+pub mod json;
+pub mod csv;
+pub mod records;
+pub mod error;
 mod test;
-mod runtimes;
+pub mod runtimes;
 mod utils;
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use crate::tf_vm::bytecode::{compile, run};
+use crate::tf_vm::env::Env;
+use crate::tf_vm::error::{Result, RuntimeError};
+use crate::tf_vm::runtimes::RuntimeValue;
+use crate::tf_vm::utils::{get_name_from_env, set_name_from_env};
+use crate::text_flow::ExprsParser;
+use crate::utils::b;
+
+pub const MODULE_EXTENSION: &str = "tf";
+pub const MODULE_PATH_VAR: &str = "TEXT_FLOW_PATH";
+
+pub fn init_modules() -> RuntimeValue {
+    let mut path = vec![b(RuntimeValue::String(b(".".to_string())))];
+    if let Some(paths) = std::env::var_os(MODULE_PATH_VAR) {
+        path.extend(std::env::split_paths(&paths).map(
+            |p| b(RuntimeValue::String(b(p.to_string_lossy().to_string())))
+        ));
+    }
+    RuntimeValue::WithEnv {
+        env: Env::from(HashMap::from([
+            ("path".to_string(), RuntimeValue::List(path)),
+            ("loaded".to_string(), RuntimeValue::WithEnv {
+                env: Env::empty(),
+                value: b(RuntimeValue::None),
+            }),
+        ]), None),
+        value: b(RuntimeValue::None),
+    }
+}
+
+fn get_registry_env(env: Arc<RwLock<Env>>, name: &str) -> Result<Arc<RwLock<Env>>> {
+    match get_name_from_env(env, name.to_string()) {
+        Some(RuntimeValue::WithEnv { env, value: _ }) => Ok(env),
+        other => Err(RuntimeError::Type(format!("module registry `{name}` must be an object, not {}", repr(other)))),
+    }
+}
+
+fn get_search_path(modules_env: Arc<RwLock<Env>>) -> Result<Vec<PathBuf>> {
+    match get_name_from_env(modules_env, "path".to_string()) {
+        Some(RuntimeValue::List(list)) => list.into_iter().map(|p| match *p {
+            RuntimeValue::String(p) => Ok(PathBuf::from(*p)),
+            p => Err(RuntimeError::Type(format!("module search path can only contain str, not {}", p.repr()))),
+        }).collect(),
+        other => Err(RuntimeError::Type(format!("module search path must be a list, not {}", repr(other)))),
+    }
+}
+
+fn repr(value: Option<RuntimeValue>) -> String {
+    value.map_or("nothing".to_string(), |v| v.repr())
+}
+
+fn resolve(path: &str, current_dir: Option<PathBuf>, search_path: Vec<PathBuf>) -> Option<PathBuf> {
+    let path = PathBuf::from(path);
+    let candidates = if path.extension().is_some() {
+        vec![path]
+    } else {
+        vec![path.clone(), path.with_extension(MODULE_EXTENSION)]
+    };
+    current_dir.into_iter().chain(search_path)
+        .flat_map(|dir| candidates.iter().map(move |c| dir.join(c)))
+        .find(|p| p.is_file())
+        .map(|p| p.canonicalize().unwrap_or(p))
+}
+
+fn load(root: Arc<RwLock<Env>>, file: &Path) -> Result<RuntimeValue> {
+    let source = std::fs::read_to_string(file)
+        .map_err(|e| RuntimeError::Io(format!("can't read module '{}': {e}", file.display())))?;
+    let ast = ExprsParser::new().parse(source.as_str())
+        .map_err(|e| RuntimeError::Value(format!("can't parse module '{}': {e}", file.display())))?;
+    let module_env = Env::from(HashMap::from([
+        ("__file__".to_string(), RuntimeValue::String(b(file.to_string_lossy().to_string())))
+    ]), Some(root));
+    if let RuntimeValue::Error(e) = *run(Arc::clone(&module_env), &compile(&ast)) {
+        return Err(e);
+    }
+    Ok(RuntimeValue::WithEnv {
+        env: module_env,
+        value: b(RuntimeValue::None),
+    })
+}
+
+// a module marked as loading is unmarked again however its loading ends, unless it was loaded
+struct Loading {
+    loaded_env: Arc<RwLock<Env>>,
+    key: String,
+}
+
+impl Drop for Loading {
+    fn drop(&mut self) {
+        let mut loaded_env = self.loaded_env.write().unwrap();
+        if let Some(RuntimeValue::None) = loaded_env.lookup(&self.key) {
+            loaded_env.remove(&self.key);
+        }
+    }
+}
+
+fn import_module(env: Arc<RwLock<Env>>, path: String, alias: Option<String>) -> Result<RuntimeValue> {
+    let modules_env = get_registry_env(Arc::clone(&env), "modules")?;
+    let loaded_env = get_registry_env(Arc::clone(&modules_env), "loaded")?;
+    let current_dir = match get_name_from_env(Arc::clone(&env), "__file__".to_string()) {
+        Some(RuntimeValue::String(file)) => Path::new(file.as_str()).parent().map(Path::to_path_buf),
+        _ => None
+    };
+    let file = resolve(path.as_str(), current_dir, get_search_path(modules_env)?)
+        .ok_or_else(|| RuntimeError::Io(format!("can't find module '{path}'")))?;
+    let key = file.to_string_lossy().to_string();
+    let module = match get_name_from_env(Arc::clone(&loaded_env), key.clone()) {
+        Some(RuntimeValue::None) => return Err(RuntimeError::Value(format!("circular import of module '{path}'"))),
+        Some(module) => module,
+        None => {
+            // mark as loading, so an import cycle finds `none` instead of recursing
+            set_name_from_env(Arc::clone(&loaded_env), key.clone(), RuntimeValue::None);
+            let _loading = Loading { loaded_env: Arc::clone(&loaded_env), key: key.clone() };
+            let root = env.read().unwrap().root();
+            let module = load(root, &file)?;
+            set_name_from_env(loaded_env, key, module.clone());
+            module
+        }
+    };
+    let name = alias.unwrap_or_else(
+        || file.file_stem().unwrap().to_string_lossy().to_string()
+    );
+    set_name_from_env(env, name, module.clone());
+    Ok(module)
+}
+
+pub fn import(env: Arc<RwLock<Env>>, path: String, alias: Option<String>) -> Box<RuntimeValue> {
+    b(import_module(env, path, alias).unwrap_or_else(RuntimeValue::Error))
+}
@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::iter::Peekable;
+use std::sync::{Arc, RwLock};
+use regex::Regex;
+use crate::Expr;
+use crate::tf_vm::env::Env;
+use crate::tf_vm::error::{Result, RuntimeError};
+use crate::tf_vm::io::stdin;
+use crate::tf_vm::iter::RuntimeIter;
+use crate::tf_vm::runtimes::RuntimeValue;
+use crate::tf_vm::bytecode::{compile, run, Chunk};
+use crate::tf_vm::vm::remove_code_pos;
+use crate::utils::b;
+
+pub enum FieldSeparator {
+    Whitespace,
+    Str(String),
+    Regex(Regex),
+}
+
+impl FieldSeparator {
+    // `/re/` is a regex, anything else a literal string, with `\t` for a tab as in awk
+    pub fn parse(separator: &str) -> Result<FieldSeparator> {
+        if separator.len() >= 2 && separator.starts_with('/') && separator.ends_with('/') {
+            let pattern = &separator[1..separator.len() - 1];
+            return Regex::new(pattern).map(FieldSeparator::Regex)
+                .map_err(|e| RuntimeError::Value(format!("bad field separator {separator}: {e}")));
+        }
+        Ok(match separator {
+            "" | " " => FieldSeparator::Whitespace,
+            "\\t" => FieldSeparator::Str("\t".to_string()),
+            _ => FieldSeparator::Str(separator.to_string()),
+        })
+    }
+
+    pub fn split<'a>(&self, record: &'a str) -> Vec<&'a str> {
+        match self {
+            FieldSeparator::Whitespace => record.split_whitespace().collect(),
+            _ if record.is_empty() => vec![],
+            FieldSeparator::Str(separator) => record.split(separator.as_str()).collect(),
+            FieldSeparator::Regex(separator) => separator.split(record).collect(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum RecordSeparator {
+    Newline,
+    // records are paragraphs, separated by blank lines
+    Paragraph,
+    Str(String),
+    Regex(Regex),
+}
+
+impl RecordSeparator {
+    // like FieldSeparator::parse, with '' for paragraphs and `\n` for the default
+    pub fn parse(separator: &str) -> Result<RecordSeparator> {
+        if separator.len() >= 2 && separator.starts_with('/') && separator.ends_with('/') {
+            let pattern = &separator[1..separator.len() - 1];
+            return Regex::new(pattern).map(RecordSeparator::Regex)
+                .map_err(|e| RuntimeError::Value(format!("bad record separator {separator}: {e}")));
+        }
+        Ok(match separator {
+            "" => RecordSeparator::Paragraph,
+            "\n" | "\\n" => RecordSeparator::Newline,
+            "\\t" => RecordSeparator::Str("\t".to_string()),
+            _ => RecordSeparator::Str(separator.to_string()),
+        })
+    }
+
+    // `RS` as set by the program, a str or a regex
+    pub fn from_value(value: &RuntimeValue) -> Result<RecordSeparator> {
+        match value {
+            RuntimeValue::String(s) => RecordSeparator::parse(s),
+            RuntimeValue::Regex(pattern) => Regex::new(pattern).map(RecordSeparator::Regex)
+                .map_err(|e| RuntimeError::Value(format!("bad record separator /{pattern}/: {e}"))),
+            value => Err(RuntimeError::Type(format!("RS must be a str or a regex, not {}", value.repr()))),
+        }
+    }
+}
+
+// reads a line at a time and hands out a record as soon as its separator has been seen
+pub struct Records<R> {
+    reader: R,
+    separator: RecordSeparator,
+    buffer: String,
+    done: bool,
+}
+
+impl<R: BufRead> Records<R> {
+    pub fn new(reader: R, separator: RecordSeparator) -> Records<R> {
+        Records { reader, separator, buffer: String::new(), done: false }
+    }
+
+    // the first record in the buffer, if it's known to be complete
+    fn split_off(&mut self) -> Option<String> {
+        let (end, next) = match &self.separator {
+            RecordSeparator::Newline => self.buffer.find('\n').map(|i| (i, i + 1))?,
+            RecordSeparator::Paragraph => {
+                let blank_lines = self.buffer.len() - self.buffer.trim_start_matches('\n').len();
+                self.buffer.drain(..blank_lines);
+                self.buffer.find("\n\n").map(|i| (i, i + 2))?
+            }
+            RecordSeparator::Str(separator) => self.buffer.find(separator.as_str()).map(|i| (i, i + separator.len()))?,
+            // a match at the end of what's read so far might still grow
+            RecordSeparator::Regex(separator) => separator.find_iter(&self.buffer)
+                .find(|m| m.start() < m.end())
+                .filter(|m| self.done || m.end() < self.buffer.len())
+                .map(|m| (m.start(), m.end()))?,
+        };
+        let mut record: String = self.buffer.drain(..next).collect();
+        record.truncate(end);
+        if let RecordSeparator::Newline = self.separator {
+            strip_suffix(&mut record, "\r");
+        }
+        Some(record)
+    }
+}
+
+fn strip_suffix(s: &mut String, suffix: &str) {
+    if s.ends_with(suffix) {
+        s.truncate(s.len() - suffix.len());
+    }
+}
+
+impl<R: BufRead> Iterator for Records<R> {
+    type Item = std::io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.split_off() {
+                return Some(Ok(record));
+            }
+            if self.done {
+                // the last record needs no separator after it, nor its line break
+                let mut record = std::mem::take(&mut self.buffer);
+                strip_suffix(&mut record, "\n");
+                strip_suffix(&mut record, "\r");
+                if let RecordSeparator::Paragraph = self.separator {
+                    record.truncate(record.trim_end_matches('\n').len());
+                }
+                return (!record.is_empty()).then_some(Ok(record));
+            }
+            match self.reader.read_line(&mut self.buffer) {
+                Ok(0) => self.done = true,
+                Ok(_) => {}
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+// a line matching `start` begins a record, the lines after it up to the next one continue it
+pub struct Continued {
+    lines: Peekable<RuntimeIter>,
+    start: Regex,
+}
+
+impl Continued {
+    pub fn new(lines: RuntimeIter, start: Regex) -> Continued {
+        Continued { lines: lines.peekable(), start }
+    }
+}
+
+impl Iterator for Continued {
+    type Item = Result<RuntimeValue>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = match self.lines.next()? {
+            Ok(line) => line.to_string(),
+            Err(e) => return Some(Err(e)),
+        };
+        while let Some(Ok(line)) = self.lines.peek() {
+            let line = line.to_string();
+            if self.start.is_match(&line) {
+                break;
+            }
+            record.push('\n');
+            record.push_str(&line);
+            self.lines.next();
+        }
+        Some(Ok(RuntimeValue::String(b(record))))
+    }
+}
+
+pub struct LineMode {
+    pub separator: FieldSeparator,
+    pub record_separator: RecordSeparator,
+    // print what the body gives for each record, unless that is none
+    pub print: bool,
+}
+
+fn int(n: usize) -> RuntimeValue {
+    RuntimeValue::Int64(n as i64)
+}
+
+fn str(s: &str) -> RuntimeValue {
+    RuntimeValue::String(b(s.to_string()))
+}
+
+// `$0` is the record, `$1..$NF` its fields, next to the awk counters
+fn record_variables(record: &str, mode: &LineMode, paragraph: bool, file: &str, nr: usize, fnr: usize) -> HashMap<String, RuntimeValue> {
+    // in paragraph mode a line break separates fields too
+    let fields: Vec<&str> = if paragraph {
+        record.lines().flat_map(|line| mode.separator.split(line)).collect()
+    } else {
+        mode.separator.split(record)
+    };
+    let mut variables = HashMap::from([
+        ("$0".to_string(), str(record)),
+        ("NR".to_string(), int(nr)),
+        ("FNR".to_string(), int(fnr)),
+        ("NF".to_string(), int(fields.len())),
+        ("FILENAME".to_string(), str(file)),
+    ]);
+    for (i, field) in fields.into_iter().enumerate() {
+        variables.insert(format!("${}", i + 1), str(field));
+    }
+    variables
+}
+
+fn open(file: &str) -> Result<Box<dyn BufRead>> {
+    if file == "-" {
+        return Ok(Box::new(stdin()));
+    }
+    std::fs::File::open(file)
+        .map(|f| Box::new(BufReader::new(f)) as Box<dyn BufRead>)
+        .map_err(|e| RuntimeError::Io(format!("can't open '{file}': {e}")))
+}
+
+pub struct Sections {
+    pub begin: Vec<Box<Expr>>,
+    pub body: Vec<Box<Expr>>,
+    pub end: Vec<Box<Expr>>,
+}
+
+pub fn split_sections(ast: Vec<Box<Expr>>) -> Sections {
+    let mut sections = Sections { begin: vec![], body: vec![], end: vec![] };
+    for expr in ast {
+        match *remove_code_pos(expr.clone()) {
+            Expr::Begin(block) => sections.begin.push(block),
+            Expr::End(block) => sections.end.push(block),
+            _ => sections.body.push(expr),
+        }
+    }
+    sections
+}
+
+// the sections compiled once, to run as often as needed
+pub struct CompiledSections {
+    begin: Chunk,
+    body: Chunk,
+    // none without an END, so the body's value stays the program's
+    end: Option<Chunk>,
+}
+
+pub fn compile_sections(ast: Vec<Box<Expr>>) -> CompiledSections {
+    let sections = split_sections(ast);
+    CompiledSections {
+        begin: compile(&sections.begin),
+        body: compile(&sections.body),
+        end: (!sections.end.is_empty()).then(|| compile(&sections.end)),
+    }
+}
+
+// BEGIN, then the rest of the program once, then END, all in `env`
+pub fn run_program(env: Arc<RwLock<Env>>, ast: Vec<Box<Expr>>) -> Box<RuntimeValue> {
+    run_compiled(env, &compile_sections(ast))
+}
+
+pub fn run_compiled(env: Arc<RwLock<Env>>, sections: &CompiledSections) -> Box<RuntimeValue> {
+    let begin = run(Arc::clone(&env), &sections.begin);
+    if let RuntimeValue::Error(_) = *begin {
+        return begin;
+    }
+    let body = run(Arc::clone(&env), &sections.body);
+    match (*body, &sections.end) {
+        (body @ RuntimeValue::Error(_), _) | (body, None) => b(body),
+        (_, Some(end)) => run(env, end),
+    }
+}
+
+// BEGIN and END run in `env`, the rest of the program once per record of each file,
+// or of stdin without files, in a record env that passes other assignments on to `env`
+pub fn run_records(env: Arc<RwLock<Env>>, ast: Vec<Box<Expr>>, files: Vec<String>, mode: &LineMode) -> Box<RuntimeValue> {
+    run_compiled_records(env, &compile_sections(ast), files, mode)
+}
+
+pub fn run_compiled_records(env: Arc<RwLock<Env>>, sections: &CompiledSections, files: Vec<String>, mode: &LineMode) -> Box<RuntimeValue> {
+    let begin = run(Arc::clone(&env), &sections.begin);
+    if let RuntimeValue::Error(_) = *begin {
+        return begin;
+    }
+    // BEGIN can set RS, as in awk
+    let separator = match env.read().unwrap().get("RS".to_string()) {
+        Some(value) => RecordSeparator::from_value(&value),
+        None => Ok(mode.record_separator.clone()),
+    };
+    let separator = match separator {
+        Ok(separator) => separator,
+        Err(e) => return b(RuntimeValue::Error(e)),
+    };
+    match (*each_record(Arc::clone(&env), &sections.body, files, mode, separator), &sections.end) {
+        (e @ RuntimeValue::Error(_), _) => b(e),
+        // the value is END's, as it is without line mode
+        (_, Some(end)) => run(env, end),
+        (_, None) => b(RuntimeValue::None),
+    }
+}
+
+fn each_record(env: Arc<RwLock<Env>>, body: &Chunk, files: Vec<String>, mode: &LineMode, separator: RecordSeparator) -> Box<RuntimeValue> {
+    let paragraph = matches!(separator, RecordSeparator::Paragraph);
+    let files = if files.is_empty() { vec!["-".to_string()] } else { files };
+    let mut nr = 0;
+    'records: for file in files {
+        let reader = match open(&file) {
+            Ok(reader) => reader,
+            Err(e) => return b(RuntimeValue::Error(e)),
+        };
+        for (fnr, record) in Records::new(reader, separator.clone()).enumerate() {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => return b(RuntimeValue::Error(RuntimeError::Io(format!("can't read '{file}': {e}")))),
+            };
+            nr += 1;
+            let record_env = Env::pass_through(record_variables(&record, mode, paragraph, &file, nr, fnr + 1), Arc::clone(&env));
+            match *run(record_env, body) {
+                // `break` stops reading and goes on to END, like awk's `exit`
+                RuntimeValue::EOF => break 'records,
+                e @ RuntimeValue::Error(_) => return b(e),
+                RuntimeValue::None => {}
+                value => if mode.print {
+                    println!("{value}")
+                }
+            }
+        }
+    }
+    // END still sees the number of records read, as in awk
+    env.write().unwrap().set("NR".to_string(), int(nr));
+    b(RuntimeValue::None)
+}
@@ -1,19 +1,27 @@
+use std::fmt::{Display, Formatter};
 use std::sync::{Arc, RwLock, RwLockReadGuard};
 use crate::{Env, Expr};
+use crate::tf_vm::bytecode::Chunk;
+use crate::tf_vm::call::Builtin;
+use crate::tf_vm::error::RuntimeError;
+use crate::tf_vm::iter::NativeIter;
 use derivative::Derivative;
 
 #[derive(Derivative)]
 #[derivative(Debug, Clone)]
 pub enum BuiltinOrExpr {
-    Builtin(fn(Arc<RwLock<Env>>) -> RuntimeValue),
+    Builtin(#[derivative(Debug = "ignore")] Builtin),
     Expr(Box<Expr>),
+    Code(#[derivative(Debug = "ignore")] Arc<Chunk>),
 }
 
 #[derive(Derivative)]
 #[derivative(Debug, Clone)]
 pub enum RuntimeValue {
+    Bool(bool),
     Int64(i64),
     Int128(i128),
+    Float(f64),
     String(Box<String>),
     Regex(Box<String>),
     List(Vec<Box<RuntimeValue>>),
@@ -31,11 +39,18 @@ pub enum RuntimeValue {
         #[derivative(Debug = "ignore")]
         env: Arc<RwLock<Env>>,
     },
+    Iter(#[derivative(Debug = "ignore")] NativeIter),
+    // a raised error, it short-circuits like EOF until `try` catches it
+    Error(RuntimeError),
 }
 
 #[derive(Derivative)]
 #[derivative(Debug, Clone)]
 pub enum RuntimeType {
+    Bool {
+        #[derivative(Debug = "ignore")]
+        env: Arc<RwLock<Env>>,
+    },
     Int64 {
         #[derivative(Debug = "ignore")]
         env: Arc<RwLock<Env>>,
@@ -44,6 +59,10 @@ pub enum RuntimeType {
         #[derivative(Debug = "ignore")]
         env: Arc<RwLock<Env>>,
     },
+    Float {
+        #[derivative(Debug = "ignore")]
+        env: Arc<RwLock<Env>>,
+    },
     String {
         #[derivative(Debug = "ignore")]
         env: Arc<RwLock<Env>>,
@@ -64,13 +83,24 @@ pub enum RuntimeType {
         #[derivative(Debug = "ignore")]
         env: Arc<RwLock<Env>>,
     },
+    Iter {
+        #[derivative(Debug = "ignore")]
+        env: Arc<RwLock<Env>>,
+    },
+    Custom {
+        name: Box<String>,
+        parent: Option<Box<RuntimeType>>,
+        #[derivative(Debug = "ignore")]
+        env: Arc<RwLock<Env>>,
+    },
 }
 
 impl RuntimeType {
     pub fn get_env(&self) -> Arc<RwLock<Env>> {
         use RuntimeType::{*};
         match self {
-            Int64 { env } | Int128 { env } | String { env } | Regex { env } | List { env } | FuncDef { env } | None { env } => {
+            Bool { env } | Int64 { env } | Int128 { env } | Float { env } | String { env } | Regex { env } | List { env } | FuncDef { env } | None { env } | Iter { env } |
+            Custom { name: _, parent: _, env } => {
                 env.clone()
             }
         }
@@ -79,37 +109,48 @@ impl RuntimeType {
     pub fn name(&self) -> String {
         use RuntimeType::{*};
         match self {
+            Bool { env: _ } => "bool".to_string(),
             Int64 { env: _ } => "i64".to_string(),
             Int128 { env: _ } => "i128".to_string(),
+            Float { env: _ } => "float".to_string(),
             String { env: _ } => "str".to_string(),
             Regex { env: _ } => "reg".to_string(),
             List { env: _ } => "list".to_string(),
             FuncDef { env: _ } => "fun".to_string(),
-            None { env: _ } => "none".to_string()
+            None { env: _ } => "none".to_string(),
+            Iter { env: _ } => "iter".to_string(),
+            Custom { name, parent: _, env: _ } => *name.clone(),
         }
     }
 }
 
 
-fn get_value_type_name(t: &RuntimeValue) -> String {
+pub fn get_value_type_name(t: &RuntimeValue) -> String {
     use RuntimeValue::{*};
     match t {
+        Bool(_) => "bool".to_string(),
         Int64(_) => "i64".to_string(),
         Int128(_) => "i128".to_string(),
+        Float(_) => "float".to_string(),
         String(_) => "str".to_string(),
         Regex(_) => "reg".to_string(),
         List(_) => "list".to_string(),
         EOF => "EOF".to_string(),
+        Error(_) => "error".to_string(),
         FuncDef { parameters: _, body: _, env: _ } => "fun".to_string(),
         None => "none".to_string(),
+        Iter(_) => "iter".to_string(),
         RuntimeType(t) => t.name(),
         WithEnv {value, env: _} => get_value_type_name(value)
     }
 }
 
+// from the builtins, so a variable named like a type doesn't hide it
 fn get_type_env(env: RwLockReadGuard<Env>, value: &RuntimeValue) -> Arc<RwLock<Env>> {
     let type_name = get_value_type_name(value);
-    match env.get(type_name.clone()).unwrap_or_else(||panic!("name '{type_name:?}' is not defined")) {
+    let builtins = env.root();
+    let type_value = builtins.read().unwrap().get(type_name.clone());
+    match type_value.unwrap_or_else(||panic!("name '{type_name:?}' is not defined")) {
         RuntimeValue::RuntimeType(t) => t.get_env(),
         _ => panic!("value is not runtimeType, is")
     }
@@ -117,19 +158,120 @@ fn get_type_env(env: RwLockReadGuard<Env>, value: &RuntimeValue) -> Arc<RwLock<E
 
 impl RuntimeValue {
     pub fn get_type(&self, env: Arc<RwLock<Env>>) -> RuntimeType {
+        match self {
+            RuntimeValue::RuntimeType(r) => return r.clone(),
+            RuntimeValue::WithEnv { env: _, value } => return value.get_type(env),
+            _ => {}
+        }
         let rw_guard_env = env.read().unwrap();
         let type_env = get_type_env(rw_guard_env, self);
         match self {
+            RuntimeValue::Bool(_) => RuntimeType::Bool { env: type_env },
             RuntimeValue::Int64(_) => RuntimeType::Int64 { env: type_env },
             RuntimeValue::Int128(_) => RuntimeType::Int128 { env: type_env },
+            RuntimeValue::Float(_) => RuntimeType::Float { env: type_env },
             RuntimeValue::String(_) => RuntimeType::String { env: type_env },
             RuntimeValue::Regex(_) => RuntimeType::Regex { env: type_env },
             RuntimeValue::List(_) => RuntimeType::List { env: type_env },
             RuntimeValue::None => RuntimeType::None { env: type_env },
             RuntimeValue::FuncDef { parameters: _, body: _, env: _ } => RuntimeType::FuncDef { env: type_env },
-            RuntimeValue::RuntimeType(r) => r.clone(),
-            RuntimeValue::WithEnv {env: _, value} => value.get_type(env),
-            RuntimeValue::EOF => panic!("EOF have no runtime")
+            RuntimeValue::Iter(_) => RuntimeType::Iter { env: type_env },
+            RuntimeValue::RuntimeType(_) | RuntimeValue::WithEnv { env: _, value: _ } => unreachable!(),
+            RuntimeValue::EOF => panic!("EOF have no runtime"),
+            RuntimeValue::Error(e) => panic!("{e}")
         }
     }
+
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            RuntimeValue::Bool(b) => *b,
+            RuntimeValue::Int64(i) => *i != 0,
+            RuntimeValue::Int128(i) => *i != 0,
+            RuntimeValue::Float(x) => *x != 0.0,
+            RuntimeValue::String(s) => !s.is_empty(),
+            RuntimeValue::List(list) => !list.is_empty(),
+            RuntimeValue::None | RuntimeValue::EOF | RuntimeValue::Error(_) => false,
+            RuntimeValue::WithEnv { value: _, env: _ } | RuntimeValue::FuncDef { parameters: _, body: _, env: _ } |
+            RuntimeValue::Regex(_) | RuntimeValue::RuntimeType(_) | RuntimeValue::Iter(_) => true,
+        }
+    }
+
+    pub fn repr(&self) -> String {
+        Repr(self).to_string()
+    }
+}
+
+fn fmt_value(value: &RuntimeValue, f: &mut Formatter<'_>, quote: bool, seen: &mut Vec<*const RwLock<Env>>) -> std::fmt::Result {
+    match value {
+        RuntimeValue::Bool(b) => write!(f, "{b}"),
+        RuntimeValue::Int64(i) => write!(f, "{i}"),
+        RuntimeValue::Int128(i) => write!(f, "{i}"),
+        // always with a point or an exponent, so it doesn't read as an int
+        RuntimeValue::Float(x) => write!(f, "{x:?}"),
+        RuntimeValue::String(s) if !quote => write!(f, "{s}"),
+        RuntimeValue::String(s) if s.contains('\'') && !s.contains('"') => write!(f, "\"{s}\""),
+        RuntimeValue::String(s) => write!(f, "'{s}'"),
+        RuntimeValue::Regex(r) => write!(f, "/{r}/"),
+        RuntimeValue::List(list) => {
+            write!(f, "[")?;
+            for (i, item) in list.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                fmt_value(item, f, true, seen)?;
+            }
+            write!(f, "]")
+        }
+        RuntimeValue::EOF => write!(f, "EOF"),
+        RuntimeValue::Error(e) => write!(f, "{e}"),
+        RuntimeValue::None => write!(f, "none"),
+        RuntimeValue::FuncDef { parameters, body: _, env: _ } => {
+            let parameters: Vec<&str> = parameters.iter().map(|p| p.as_str()).collect();
+            write!(f, "f[{}]{{..}}", parameters.join(", "))
+        }
+        RuntimeValue::RuntimeType(t) => write!(f, "{}", t.name()),
+        RuntimeValue::Iter(_) => write!(f, "iter[..]"),
+        RuntimeValue::WithEnv { value, env } => {
+            match value.as_ref() {
+                RuntimeValue::None => {}
+                RuntimeValue::RuntimeType(t) => write!(f, "{}", t.name())?,
+                value => {
+                    write!(f, "obj[")?;
+                    fmt_value(value, f, true, seen)?;
+                    write!(f, "]")?;
+                }
+            }
+            // an object can contain itself
+            if seen.contains(&Arc::as_ptr(env)) {
+                return write!(f, "{{..}}");
+            }
+            seen.push(Arc::as_ptr(env));
+            let mut variables: Vec<(String, RuntimeValue)> = env.read().unwrap().variables().into_iter().collect();
+            variables.sort_by(|(x, _), (y, _)| x.cmp(y));
+            write!(f, "{{")?;
+            for (i, (name, value)) in variables.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{name}: ")?;
+                fmt_value(value, f, true, seen)?;
+            }
+            seen.pop();
+            write!(f, "}}")
+        }
+    }
+}
+
+struct Repr<'a>(&'a RuntimeValue);
+
+impl Display for Repr<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        fmt_value(self.0, f, true, &mut vec![])
+    }
+}
+
+impl Display for RuntimeValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        fmt_value(self, f, false, &mut vec![])
+    }
 }
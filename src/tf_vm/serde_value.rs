@@ -0,0 +1,495 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, RwLock};
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use crate::tf_vm::env::Env;
+use crate::tf_vm::error::RuntimeError;
+use crate::tf_vm::runtimes::{get_value_type_name, RuntimeValue};
+use crate::utils::b;
+
+// what doesn't fit the value, or the value the other way, is a value error in rust and a type error in a program
+impl ser::Error for RuntimeError {
+    fn custom<T: Display>(message: T) -> Self {
+        RuntimeError::Value(message.to_string())
+    }
+}
+
+impl de::Error for RuntimeError {
+    fn custom<T: Display>(message: T) -> Self {
+        RuntimeError::Type(message.to_string())
+    }
+}
+
+fn object(fields: HashMap<String, RuntimeValue>) -> RuntimeValue {
+    RuntimeValue::WithEnv { env: Env::from(fields, None), value: b(RuntimeValue::None) }
+}
+
+fn int(n: i128) -> RuntimeValue {
+    i64::try_from(n).map_or(RuntimeValue::Int128(n), RuntimeValue::Int64)
+}
+
+// a rust value as a runtime value: structs and maps become objects, sequences and tuples lists,
+// an enum variant with data an object of one field named after it, as serde_json lays them out
+pub struct Serializer;
+
+pub struct SerializeList(Vec<Box<RuntimeValue>>);
+
+pub struct SerializeObject {
+    fields: HashMap<String, RuntimeValue>,
+    key: Option<String>,
+}
+
+pub struct SerializeVariant<S> {
+    name: &'static str,
+    inner: S,
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = RuntimeValue;
+    type Error = RuntimeError;
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeVariant<SerializeList>;
+    type SerializeMap = SerializeObject;
+    type SerializeStruct = SerializeObject;
+    type SerializeStructVariant = SerializeVariant<SerializeObject>;
+
+    fn serialize_bool(self, v: bool) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::Int64(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::Int64(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::Int64(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::Int64(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<RuntimeValue, RuntimeError> {
+        Ok(int(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::Int64(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::Int64(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::Int64(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<RuntimeValue, RuntimeError> {
+        Ok(int(v.into()))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<RuntimeValue, RuntimeError> {
+        i128::try_from(v).map(int).map_err(|_| RuntimeError::Value(format!("{v} is too big for an int")))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::Float(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::String(b(v.to_string())))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::String(b(v.to_string())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::List(v.iter().map(|byte| b(RuntimeValue::Int64((*byte).into()))).collect()))
+    }
+
+    fn serialize_none(self) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<RuntimeValue, RuntimeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::None)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::String(b(variant.to_string())))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<RuntimeValue, RuntimeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<RuntimeValue, RuntimeError> {
+        Ok(object(HashMap::from([(variant.to_string(), value.serialize(Serializer)?)])))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeList, RuntimeError> {
+        Ok(SerializeList(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList, RuntimeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeList, RuntimeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeList>, RuntimeError> {
+        Ok(SerializeVariant { name: variant, inner: self.serialize_seq(Some(len))? })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeObject, RuntimeError> {
+        Ok(SerializeObject { fields: HashMap::new(), key: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeObject, RuntimeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeObject>, RuntimeError> {
+        Ok(SerializeVariant { name: variant, inner: self.serialize_map(Some(len))? })
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = RuntimeValue;
+    type Error = RuntimeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        self.0.push(b(value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<RuntimeValue, RuntimeError> {
+        Ok(RuntimeValue::List(self.0))
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = RuntimeValue;
+    type Error = RuntimeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<RuntimeValue, RuntimeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = RuntimeValue;
+    type Error = RuntimeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<RuntimeValue, RuntimeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeList> {
+    type Ok = RuntimeValue;
+    type Error = RuntimeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        ser::SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<RuntimeValue, RuntimeError> {
+        Ok(object(HashMap::from([(self.name.to_string(), ser::SerializeSeq::end(self.inner)?)])))
+    }
+}
+
+impl ser::SerializeMap for SerializeObject {
+    type Ok = RuntimeValue;
+    type Error = RuntimeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), RuntimeError> {
+        self.key = Some(match key.serialize(Serializer)? {
+            RuntimeValue::String(key) => *key,
+            // as in json, a number or a bool key is written out
+            key @ (RuntimeValue::Int64(_) | RuntimeValue::Int128(_) | RuntimeValue::Bool(_)) => key.to_string(),
+            key => return Err(RuntimeError::Value(format!("an object's keys must be strings, not {}", key.repr()))),
+        });
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        let key = self.key.take().expect("serialize_value called before serialize_key");
+        self.fields.insert(key, value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<RuntimeValue, RuntimeError> {
+        Ok(object(self.fields))
+    }
+}
+
+impl ser::SerializeStruct for SerializeObject {
+    type Ok = RuntimeValue;
+    type Error = RuntimeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), RuntimeError> {
+        self.fields.insert(key.to_string(), value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<RuntimeValue, RuntimeError> {
+        Ok(object(self.fields))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeObject> {
+    type Ok = RuntimeValue;
+    type Error = RuntimeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), RuntimeError> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<RuntimeValue, RuntimeError> {
+        Ok(object(HashMap::from([(self.name.to_string(), ser::SerializeStruct::end(self.inner)?)])))
+    }
+}
+
+// the objects a value is inside of, by address
+type Seen = Vec<*const RwLock<Env>>;
+
+// reads a rust value out of a runtime value laid out as `Serializer` writes them;
+// `seen` are the objects around this one, an object inside itself can't be read
+pub struct Deserializer<'a> {
+    value: &'a RuntimeValue,
+    seen: Seen,
+}
+
+impl<'a> Deserializer<'a> {
+    pub fn new(value: &'a RuntimeValue) -> Deserializer<'a> {
+        Deserializer { value, seen: vec![] }
+    }
+
+    fn inner<'b>(&self, value: &'b RuntimeValue) -> Deserializer<'b> {
+        Deserializer { value, seen: self.seen.clone() }
+    }
+
+    // an object's own fields, with this object among the ones seen
+    fn fields(&self, env: &Arc<RwLock<Env>>) -> Result<(Vec<(String, RuntimeValue)>, Seen), RuntimeError> {
+        if self.seen.contains(&Arc::as_ptr(env)) {
+            return Err(RuntimeError::Value("can't read an object that contains itself".to_string()));
+        }
+        let mut seen = self.seen.clone();
+        seen.push(Arc::as_ptr(env));
+        Ok((env.read().unwrap().variables().into_iter().collect(), seen))
+    }
+}
+
+fn is_object(value: &RuntimeValue) -> bool {
+    matches!(value, RuntimeValue::WithEnv { value, env: _ } if !matches!(value.as_ref(), RuntimeValue::FuncDef { .. }))
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'_> {
+    type Error = RuntimeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RuntimeError> {
+        match self.value {
+            RuntimeValue::None => visitor.visit_unit(),
+            RuntimeValue::Bool(x) => visitor.visit_bool(*x),
+            RuntimeValue::Int64(n) => visitor.visit_i64(*n),
+            // only a u64 can hold an int past i64, and serde's visitors don't take an i128 for it
+            RuntimeValue::Int128(n) => match u64::try_from(*n) {
+                Ok(n) => visitor.visit_u64(n),
+                Err(_) => visitor.visit_i128(*n),
+            },
+            RuntimeValue::Float(x) => visitor.visit_f64(*x),
+            RuntimeValue::String(s) => visitor.visit_str(s),
+            RuntimeValue::List(list) => visitor.visit_seq(SeqAccess { deserializer: &self, values: list.iter() }),
+            RuntimeValue::WithEnv { value: _, env } if is_object(self.value) => {
+                let (fields, seen) = self.fields(env)?;
+                visitor.visit_map(MapAccess { fields: fields.into_iter(), value: None, seen })
+            }
+            value => Err(RuntimeError::Type(format!("can't read a {} as a rust value", get_value_type_name(value)))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RuntimeError> {
+        match self.value {
+            RuntimeValue::None => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, RuntimeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // a unit variant by its name, any other by an object of one field named after it
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, RuntimeError> {
+        match self.value {
+            RuntimeValue::String(s) => visitor.visit_enum(s.as_str().into_deserializer()),
+            RuntimeValue::WithEnv { value: _, env } if is_object(self.value) => {
+                let (mut fields, seen) = self.fields(env)?;
+                match fields.pop() {
+                    Some((variant, value)) if fields.is_empty() => visitor.visit_enum(EnumAccess { variant, value, seen }),
+                    _ => Err(RuntimeError::Type("an enum variant must be an object of exactly one field".to_string())),
+                }
+            }
+            value => Err(RuntimeError::Type(format!("expected an enum variant, not {}", value.repr()))),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct SeqAccess<'a, 'b> {
+    deserializer: &'a Deserializer<'a>,
+    values: std::slice::Iter<'b, Box<RuntimeValue>>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess<'_, '_> {
+    type Error = RuntimeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, RuntimeError> {
+        match self.values.next() {
+            Some(value) => seed.deserialize(self.deserializer.inner(value)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len())
+    }
+}
+
+struct MapAccess {
+    fields: std::vec::IntoIter<(String, RuntimeValue)>,
+    value: Option<RuntimeValue>,
+    seen: Seen,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = RuntimeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, RuntimeError> {
+        match self.fields.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(key.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, RuntimeError> {
+        let value = self.value.take().expect("next_value_seed called before next_key_seed");
+        seed.deserialize(Deserializer { value: &value, seen: self.seen.clone() })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len())
+    }
+}
+
+struct EnumAccess {
+    variant: String,
+    value: RuntimeValue,
+    seen: Seen,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = RuntimeError;
+    type Variant = VariantAccess;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, VariantAccess), RuntimeError> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, VariantAccess { value: self.value, seen: self.seen }))
+    }
+}
+
+struct VariantAccess {
+    value: RuntimeValue,
+    seen: Seen,
+}
+
+impl VariantAccess {
+    fn deserializer(&self) -> Deserializer<'_> {
+        Deserializer { value: &self.value, seen: self.seen.clone() }
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess {
+    type Error = RuntimeError;
+
+    fn unit_variant(self) -> Result<(), RuntimeError> {
+        match self.value {
+            RuntimeValue::None => Ok(()),
+            value => Err(RuntimeError::Type(format!("a unit variant has no value, not {}", value.repr()))),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, RuntimeError> {
+        seed.deserialize(self.deserializer())
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, RuntimeError> {
+        de::Deserializer::deserialize_seq(self.deserializer(), visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, RuntimeError> {
+        de::Deserializer::deserialize_map(self.deserializer(), visitor)
+    }
+}
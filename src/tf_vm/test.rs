@@ -1,4 +1,561 @@
 #[cfg(test)]
-#[test]
-fn text_flow() {
-}
\ No newline at end of file
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::{Arc, RwLock};
+    use crate::tf_vm::builtins::init_builtin;
+    use crate::tf_vm::env::Env;
+    use crate::tf_vm::iter::{to_runtime_iter, RuntimeIter};
+    use crate::tf_vm::records::{run_program, run_records, FieldSeparator, LineMode, RecordSeparator, Records};
+    use crate::tf_vm::utils::set_name_from_env;
+    use crate::tf_vm::runtimes::RuntimeValue;
+    use crate::tf_vm::bytecode::{compile, run as run_code};
+    use crate::tf_vm::vm::eval;
+    use crate::text_flow::{ExprsParser, ProgramParser};
+
+    // on both engines, which have to agree
+    fn run(code: &str) -> RuntimeValue {
+        let value = run_in(Env::new(Some(init_builtin())), code);
+        assert_eq!(run_compiled(code), value.repr(), "{code}");
+        value
+    }
+
+    fn run_in(env: Arc<RwLock<Env>>, code: &str) -> RuntimeValue {
+        let ast = ExprsParser::new().parse(code).unwrap();
+        *eval(env, ast)
+    }
+
+    // what the tree walker gives
+    fn walk(code: &str) -> String {
+        let ast = ExprsParser::new().parse(code).unwrap();
+        eval(Env::new(Some(init_builtin())), ast).repr()
+    }
+
+    fn run_compiled(code: &str) -> String {
+        let ast = ExprsParser::new().parse(code).unwrap();
+        run_code(Env::new(Some(init_builtin())), &compile(&ast)).repr()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("text-flow-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn text_flow() {
+        assert!(matches!(run("a = obj[1]; a.x = 3; a.x"), RuntimeValue::Int64(3)));
+        assert!(matches!(run("g = f[a, b]{a * b}; g[2, b=5]"), RuntimeValue::Int64(10)));
+    }
+
+    #[test]
+    fn import() {
+        let dir = temp_dir("import");
+        std::fs::write(dir.join("math.tf"), "double = f[x]{x * 2}; ten = 10").unwrap();
+        std::fs::write(dir.join("uses_math.tf"), "import 'math.tf'; twenty = math.double[math.ten]").unwrap();
+        let dir = dir.to_string_lossy();
+        assert!(matches!(
+            run(&format!("import '{dir}/math.tf'; math.double[math.ten]")),
+            RuntimeValue::Int64(20)
+        ));
+        assert!(matches!(
+            run(&format!("import '{dir}/math' as m; m.ten")),
+            RuntimeValue::Int64(10)
+        ));
+        assert!(matches!(
+            run(&format!("import '{dir}/uses_math'; uses_math.twenty")),
+            RuntimeValue::Int64(20)
+        ));
+        assert!(matches!(
+            run(&format!("modules.path = ['{dir}']; import 'math'; math.ten")),
+            RuntimeValue::Int64(10)
+        ));
+    }
+
+    #[test]
+    fn import_is_cached() {
+        let dir = temp_dir("import_is_cached");
+        std::fs::write(dir.join("counter.tf"), "n = 0").unwrap();
+        let dir = dir.to_string_lossy();
+        assert!(matches!(
+            run(&format!("import '{dir}/counter'; counter.n = 1; import '{dir}/counter.tf' as c; c.n")),
+            RuntimeValue::Int64(1)
+        ));
+    }
+
+    #[test]
+    fn import_cycle() {
+        let dir = temp_dir("import_cycle");
+        std::fs::write(dir.join("a.tf"), "import 'b'").unwrap();
+        std::fs::write(dir.join("b.tf"), "import 'a'").unwrap();
+        let dir = dir.to_string_lossy();
+        assert!(run(&format!("import '{dir}/a'")).to_string().contains("circular import of module 'a'"));
+        // a failed import isn't cached, so fixing the module is enough to import it
+        let env = Env::new(Some(init_builtin()));
+        std::fs::write(format!("{dir}/c.tf"), "x = read['/no/such/file']").unwrap();
+        assert!(matches!(run_in(Arc::clone(&env), &format!("import '{dir}/c'")), RuntimeValue::Error(_)));
+        std::fs::write(format!("{dir}/c.tf"), "x = 1").unwrap();
+        assert!(matches!(run_in(env, &format!("import '{dir}/c'; c.x")), RuntimeValue::Int64(1)));
+    }
+
+    #[test]
+    fn destructure() {
+        assert!(matches!(run("[a, b] = [1, 2]; a + b"), RuntimeValue::Int64(3)));
+        assert!(matches!(run("[a, [b, c]] = [1, [2, 3]]; c"), RuntimeValue::Int64(3)));
+        assert!(matches!(run("[head, *rest] = [1, 2, 3]; rest.len[]"), RuntimeValue::Int64(2)));
+        assert!(matches!(run("[a, *mid, z] = [1, 2]; [mid.len[], z]"), RuntimeValue::List(v) if v.len() == 2));
+        assert!(matches!(run("{x, y: [p, q]} = {x: 1, y: [2, 3]}; x + q"), RuntimeValue::Int64(4)));
+        assert!(matches!(run("[_, second] = [1, 2]; second"), RuntimeValue::Int64(2)));
+        assert!(matches!(run("[1, *[2, 3], 4].len[]"), RuntimeValue::Int64(4)));
+        assert_eq!(walk("{str,} = {a: 1,}; str"), "name error: can't destructure, the object has no field `str`");
+        assert_eq!(run("g = f[[a, b]]{a}; g[]").to_string(), "type error: argument 1 is missing, the function destructures it");
+        assert_eq!(run("g = f[x, {y,}]{y}; g[1]").to_string(), "type error: argument 2 is missing, the function destructures it");
+    }
+
+    #[test]
+    fn destructure_parameters() {
+        assert!(matches!(run("g = f[[a, b], c]{a + b + c}; g[[1, 2], 3]"), RuntimeValue::Int64(6)));
+        assert!(matches!(run("g = f[{x, y}]{x * y}; g[{x: 2, y: 5}]"), RuntimeValue::Int64(10)));
+        assert!(matches!(run("g = f[{x}, [{y}]]{x * y}; g[{x: 2,}, [{y: 5,}]]"), RuntimeValue::Int64(10)));
+        assert!(matches!(run("{x} = {x: 3,}; x"), RuntimeValue::Int64(3)));
+        assert!(matches!(run("match {x: 4,} { {x} => x }"), RuntimeValue::Int64(4)));
+    }
+
+    #[test]
+    fn destructure_arity() {
+        assert_eq!(run("[a, b] = [1, 2, 3]").to_string(), "value error: can't destructure 3 values into 2 names");
+    }
+
+    #[test]
+    fn compare() {
+        assert!(matches!(run("1 == 1 && 'a' != 'b'"), RuntimeValue::Bool(true)));
+        assert!(matches!(run("x = 2 > 1 || 1 > 2; x"), RuntimeValue::Bool(true)));
+        assert!(matches!(run("-1 + 2 <= 0"), RuntimeValue::Bool(false)));
+        assert!(matches!(run("!([1, 2] < [1, 3])"), RuntimeValue::Bool(false)));
+        assert!(matches!(run("0 || 'default'"), RuntimeValue::String(s) if s.as_str() == "default"));
+    }
+
+    #[test]
+    fn match_patterns() {
+        let classify = "classify = f[line]{ match line {
+            /^(?P<level>ERROR|WARN) (?P<msg>.*)$/ if level == 'ERROR' => msg,
+            /^WARN/ => 'warning',
+            [a, *rest] => rest.len[],
+            {name,} => name,
+            n: i64 if n > 10 => 'big',
+            i64 => 'small',
+            'x' => 'literal',
+            _ => 'other'
+        }}; ";
+        let cases = [
+            ("classify['ERROR disk full']", "disk full"),
+            ("classify['WARN low memory']", "warning"),
+            ("classify[{name: 'tf',}]", "tf"),
+            ("classify[11]", "big"),
+            ("classify[1]", "small"),
+            ("classify['x']", "literal"),
+            ("classify['y']", "other"),
+        ];
+        for (call, expected) in cases {
+            match run(&(classify.to_string() + call)) {
+                RuntimeValue::String(s) => assert_eq!(s.as_str(), expected),
+                value => panic!("{call} returned {value:?}")
+            }
+        }
+        assert!(matches!(run(&(classify.to_string() + "classify[[1, 2, 3]]")), RuntimeValue::Int64(2)));
+        assert!(matches!(run("match 1 { 2 => 2 }"), RuntimeValue::None));
+        assert_eq!(run("match {a: 1,} { {str,} => 'str', {a,} => a }").to_string(), "1");
+        // bindings stay in their arm, even when its guard fails, and a guard's error isn't a mismatch
+        assert_eq!(run("n = 'outer'; match 1 { n if n > 5 => n, _ => n }").to_string(), "outer");
+        assert!(matches!(run("match 1 { n => { m = n + 1 } }; m"), RuntimeValue::Int64(2)));
+        assert!(matches!(run("match 1 { n if read['/no/such/file'] => n, _ => 0 }"), RuntimeValue::Error(_)));
+    }
+
+    #[test]
+    fn types() {
+        let animals = "
+            greet = f[name]{ 'hello ' + name };
+            type Animal {
+                init = f[self, name]{ self.name = name };
+                speak = f[self]{ greet[self.name] };
+                legs = 4
+            };
+            type Bird(Animal) {
+                init = f[self, name]{ super.init[name]; self.legs = 2 };
+                speak = f[self]{ super.speak[] + ', tweet' }
+            };
+        ";
+        let cases = [
+            ("Animal['rex'].speak[]", "hello rex"),
+            ("Bird['tweety'].speak[]", "hello tweety, tweet"),
+            ("Bird['tweety'].type[]", "Bird"),
+            ("Animal.type[]", "Animal"),
+        ];
+        for (code, expected) in cases {
+            match run(&(animals.to_string() + code)) {
+                RuntimeValue::String(s) => assert_eq!(s.as_str(), expected),
+                value => panic!("{code} returned {value:?}")
+            }
+        }
+        assert!(matches!(run(&(animals.to_string() + "[Animal['a'].legs, Bird['b'].legs]")),
+            RuntimeValue::List(v) if matches!((v[0].as_ref(), v[1].as_ref()), (RuntimeValue::Int64(4), RuntimeValue::Int64(2)))));
+        // a subtype's instance is an instance of its parent type too, but not the other way round
+        assert!(matches!(run(&(animals.to_string() + "match Bird['b'] { a: Animal => 1, b: Bird => 2 }")), RuntimeValue::Int64(1)));
+        assert!(matches!(run(&(animals.to_string() + "match Bird['b'] { Animal => 1, _ => 2 }")), RuntimeValue::Int64(1)));
+        assert!(matches!(run(&(animals.to_string() + "match Animal['a'] { b: Bird => 2, a: Animal => 1 }")), RuntimeValue::Int64(1)));
+    }
+
+    #[test]
+    fn operators() {
+        let vector = "
+            type V {
+                init = f[self, x, y]{ self.x = x; self.y = y };
+                add = f[self, o]{ V[self.x + o.x, self.y + o.y] };
+                mul = f[self, k]{ V[self.x * k, self.y * k] };
+                eq = f[self, o]{ self.x == o.x && self.y == o.y };
+                lt = f[self, o]{ self.x < o.x }
+            };
+        ";
+        assert!(matches!(run(&(vector.to_string() + "((V[1, 2] + V[3, 4]) * 2).y")), RuntimeValue::Int64(12)));
+        assert!(matches!(run(&(vector.to_string() + "V[1, 1] == V[1, 1] && V[1, 1] != V[1, 2]")), RuntimeValue::Bool(true)));
+        assert!(matches!(run(&(vector.to_string() + "V[0, 9] < V[1, 0]")), RuntimeValue::Bool(true)));
+        assert!(matches!(run("([1, 2] + [3]).len[]"), RuntimeValue::Int64(3)));
+        assert!(matches!(run("([0, 1] * 3).len[]"), RuntimeValue::Int64(6)));
+        assert!(matches!(run("'ab' * 2"), RuntimeValue::String(s) if s.as_str() == "abab"));
+        assert!(matches!(run("3 * '-'"), RuntimeValue::String(s) if s.as_str() == "---"));
+        // overflow and impossible repeats are errors a program can catch, not crashes
+        assert_eq!(run("170141183460469231731687303715884105727 + 1").to_string(), "value error: 170141183460469231731687303715884105727 + 1 overflows");
+        assert_eq!(run("1 / 0").to_string(), "value error: division by zero");
+        assert!(matches!(run("[1, 2] * 9223372036854775807"), RuntimeValue::Error(_)));
+        assert!(matches!(run("9223372036854775807 + 1"), RuntimeValue::Int128(_)));
+        assert!(matches!(run("[1, 2].iter[].count[] == [1, 2].len[]"), RuntimeValue::Bool(true)));
+    }
+
+    #[test]
+    fn display() {
+        let cases = [
+            ("'plain'", "plain"),
+            ("[1, 'a', \"it's\", /x+/, [true, none]]", "[1, 'a', \"it's\", /x+/, [true, none]]"),
+            ("{b: 2, a: 'x',}", "{a: 'x', b: 2}"),
+            ("f[a, b]{a}", "f[a, b]{..}"),
+            ("type P { init = f[self, x]{ self.x = x } }; P[1]", "P{x: 1}"),
+            ("type P { str = f[self]{ 'a P' } }; type Q(P) {}; [Q[].str[], Q[].type[]]", "['a P', 'Q']"),
+            ("o = {a: 1,}; o.me = o; o", "{a: 1, me: {..}}"),
+            ("[1.str[], 'a'.repr[], [1, 'b'].str[], str]", "['1', \"'a'\", \"[1, 'b']\", str]"),
+        ];
+        for (code, expected) in cases {
+            assert_eq!(run(code).to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn iter_bridge() {
+        let ints = |values: Vec<RuntimeValue>| values.iter().map(|v| match v {
+            RuntimeValue::Int64(i) => *i,
+            value => panic!("not an int: {value:?}")
+        }).collect::<Vec<i64>>();
+        let collect = |env: Arc<RwLock<Env>>, code: &str| {
+            let value = run_in(Arc::clone(&env), code);
+            RuntimeIter::new(env, value).unwrap().collect::<Result<Vec<RuntimeValue>, _>>().unwrap()
+        };
+        let env = Env::new(Some(init_builtin()));
+        assert_eq!(ints(collect(Arc::clone(&env), "[1, 2, 3]")), vec![1, 2, 3]);
+        assert_eq!(ints(collect(Arc::clone(&env), "[4, 5].iter[]")), vec![4, 5]);
+        let wrapper = "type Wrap { init = f[self, xs]{ self.it = xs.iter[] }; next = f[self]{ match self.it.next[] { n: i64 => n, _ => break } } }; Wrap[[6, 7]]";
+        assert_eq!(ints(collect(Arc::clone(&env), wrapper)), vec![6, 7]);
+        assert!(RuntimeIter::new(Arc::clone(&env), RuntimeValue::Int64(1)).is_err());
+        assert!(matches!(run("m = [1, 2] -< f[i]{i * 10} -< f[i]{i + 1}; [m.next[], m.next[]]"),
+            RuntimeValue::List(v) if matches!((v[0].as_ref(), v[1].as_ref()), (RuntimeValue::Int64(11), RuntimeValue::Int64(21)))));
+
+        set_name_from_env(Arc::clone(&env), "nums".to_string(), to_runtime_iter((1..=3).map(|i| Ok(RuntimeValue::Int64(i)))));
+        assert!(matches!(run_in(Arc::clone(&env), "nums.next[]"), RuntimeValue::Int64(1)));
+        assert_eq!(ints(collect(Arc::clone(&env), "nums.iter[]")), vec![2, 3]);
+        assert!(matches!(run_in(env, "nums.next[]"), RuntimeValue::None));
+        assert_eq!(run("[].iter[]").to_string(), "iter[..]");
+    }
+
+    #[test]
+    fn iter_combinators() {
+        let cases = [
+            ("([1, 2, 3, 4, 5, 6] -< f[i]{i * 10}).filter[f[x]{x > 20}].skip[1].take[2].sum[]", "90"),
+            ("[1, 2, 3].iter[].zip[[4, 5, 6]].enumerate[].last[]", "[2, [3, 6]]"),
+            ("[1, 2, 3, 4].iter[].windows[3].first[]", "[1, 2, 3]"),
+            ("[1, 2, 3, 4, 5].iter[].chunks[2].last[]", "[5]"),
+            ("[1, 2, 3, 4].iter[].step_by[2].chain[[9]].count[]", "3"),
+            // a receiver that isn't iterable is an error, not a crash
+            ("[].iter[].count[self=1]", "type error: 1 is not iterable"),
+            ("try[f[]{[].iter[].filter[f[x]{x}, self=1]}, f[e]{e.kind}]", "type"),
+            ("[[1, 2], [3]].iter[].flat_map[f[x]{x}].reduce[f[a, c]{a * 10 + c}]", "123"),
+            ("[1, 2].iter[].reduce[f[a, c]{a + c}, 10]", "13"),
+            ("[5, 1, 9, 2].iter[].take_while[f[x]{x < 9}].max[]", "5"),
+            ("[1, 0].iter[].filter[f[x]{1 / x}].count[]", "value error: division by zero"),
+            ("[1, 0].iter[].take_while[f[x]{1 / x}].count[]", "value error: division by zero"),
+            ("['b', 'a'].iter[].min[]", "a"),
+            ("[1, 0].iter[].any[]", "true"),
+            ("[1, 0].iter[].all[f[x]{x < 2}]", "true"),
+            ("[].iter[].first[]", "none"),
+            ("p = [1, 2].iter[].peekable[]; [p.peek[], p.next[], p.next[]]", "[1, 1, 2]"),
+        ];
+        for (code, expected) in cases {
+            assert_eq!(run(code).to_string(), expected, "{code}");
+        }
+    }
+
+    #[test]
+    fn end_of_stream() {
+        let down = "type Down {
+            init = f[self, n]{ self.n = n };
+            next = f[self]{ match self.n { 0 => break, n => { self.n = n - 1; n } } }
+        }; ";
+        let cases = [
+            ("([1, 2] -< f[i]{i * 2} -< f[i]{i + 1} -< f[i]{i * 10}).count[]", "2"),
+            ("([1, 2, 3] -< f[i]{ match i { 3 => break, _ => i } }).sum[]", "3"),
+            ("(Down[3] -< f[i]{i * 10}).sum[]", "60"),
+            ("type Wrap { init = f[self, it]{ self.it = it }; next = f[self]{ match self.it.next[] { n: i64 => n + 1, _ => break } } }; (Wrap[Down[2]] -< f[i]{i}).last[]", "2"),
+            ("it = [1].iter[]; it.next[]; seen = [it.next[]]; seen", "[none]"),
+            ("d = Down[1]; [d.next[], d.next[], 'after']", "[1, none, 'after']"),
+            ("g = f[]{ break }; [g[], 'after']", "[none, 'after']"),
+        ];
+        for (code, expected) in cases {
+            assert_eq!(run(&(down.to_string() + code)).to_string(), expected, "{code}");
+        }
+    }
+
+    #[test]
+    fn map_parameters() {
+        let cases = [
+            ("(['a', 'b'] -< f[line]{line + '!'}).last[]", "b!"),
+            ("(['a', 'b'] -< f[line, n]{n.str[] + line}).last[]", "1b"),
+            ("([1, 2] -< f[]{i * 10}).sum[]", "30"),
+            ("([1, 2] -< {i + 1}).sum[]", "5"),
+            ("type C { scale = f[self, x]{x * 2} }; c = C[]; ([1, 2] -< c.scale).sum[]", "6"),
+        ];
+        for (code, expected) in cases {
+            assert_eq!(run(code).to_string(), expected, "{code}");
+        }
+    }
+
+    #[test]
+    fn streams() {
+        let dir = temp_dir("streams");
+        let file = dir.join("log.txt");
+        std::fs::write(&file, "a\nbb\r\nccc\n").unwrap();
+        let file = file.to_string_lossy();
+        let cases = [
+            (format!("lines['{file}'] >- list"), "['a', 'bb', 'ccc']"),
+            (format!("lines['{file}'] -< f[l, n]{{n.str[] + l}} >- list"), "['0a', '1bb', '2ccc']"),
+            (format!("seen = {{items: [],}}; lines['{file}'] >- f[l]{{ match l {{ 'ccc' => break, _ => seen.items = seen.items + [l] }} }}; seen.items"), "['a', 'bb']"),
+            ("[1, 2] -< {i * 2} >- list".to_string(), "[2, 4]"),
+        ];
+        for (code, expected) in cases {
+            assert_eq!(run(&code).to_string(), expected, "{code}");
+        }
+    }
+
+    #[test]
+    fn files() {
+        let dir = temp_dir("files");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub").join("b.log"), "bb").unwrap();
+        let dir = dir.to_string_lossy();
+        let cases = [
+            (format!("write['{dir}/a.txt', 1]; append['{dir}/a.txt', ' two']; read['{dir}/a.txt']"), "1 two"),
+            (format!("[exists['{dir}/a.txt'], exists['{dir}/missing']]"), "[true, false]"),
+            (format!("glob['{dir}/*/*.log'] -< f[p]{{read[p]}} >- list"), "['bb']"),
+            (format!("(walk['{dir}'].filter[f[p]{{!p.is_dir}}] -< f[p]{{p.size}}).sum[]"), "7"),
+            (format!("walk['{dir}'].filter[f[p]{{p.is_dir}}].first[].name"), "sub"),
+            (format!("try[f[]{{read['{dir}/missing']}}, f[e]{{e.kind}}]"), "io"),
+            (format!("try[f[]{{ x = read['{dir}/missing']; 'not reached' }}]"), "none"),
+            (format!("try[f[]{{ ['a.txt', 'missing'] -< f[p]{{read['{dir}/' + p]}} >- list }}, f[e]{{e.kind}}]"), "io"),
+            ("try[f[]{glob['[']}, f[e]{e.kind}]".to_string(), "value"),
+            ("try[f[]{1}, f[e]{2}]".to_string(), "1"),
+        ];
+        for (code, expected) in cases {
+            assert_eq!(run(&code).to_string(), expected, "{code}");
+        }
+        assert!(matches!(run("read['/no/such/file']"), RuntimeValue::Error(_)));
+    }
+
+    #[test]
+    fn script_errors() {
+        let cases = [
+            ("undefined_name", "name error: `undefined_name` is not defined"),
+            ("try[f[]{undefined_name}, f[e]{e.kind}]", "name"),
+            ("{a: 1,}.b", "name error: {a: 1} has no field `b`"),
+            ("1 + 'a'", "type error: can't use + on 1 and 'a'"),
+            ("1 < 'a'", "type error: can't compare 1 and 'a'"),
+            ("-'a'", "type error: can't negate 'a'"),
+            ("~1", "type error: the `~` operator isn't supported yet"),
+            ("g = f[a]{a}; g[1, 2]", "type error: the function takes 1 arguments, but 2 were given"),
+            ("1[]", "type error: can't call 1, it's not a function"),
+            ("[1].5", "value error: index 5 is out of range for a list of 1"),
+            ("type T(1) {}", "type error: type T can only inherit from a type, not 1"),
+            ("type T { init = f[self]{ nope } }; T[]", "name error: `nope` is not defined"),
+            ("type T {}; T[1]", "type error: the function takes 0 arguments, but 1 were given"),
+            ("type T {}; T[x=1]", "type error: the function takes 0 arguments, but 1 were given"),
+            ("try[f[]{ 1 + 'a' }, f[e]{ e.kind }]", "type"),
+            ("list = 1; [].len[]", "0"),
+        ];
+        for (code, expected) in cases {
+            assert_eq!(run(code).to_string(), expected, "{code}");
+        }
+    }
+
+    #[test]
+    fn line_mode() {
+        let dir = temp_dir("line_mode");
+        std::fs::write(dir.join("a.txt"), "x 1\n\ny  2 z\n").unwrap();
+        std::fs::write(dir.join("b.txt"), "w:3\nstop\nnot read\n").unwrap();
+        let files: Vec<String> = ["a.txt", "b.txt"].iter().map(|f| dir.join(f).to_string_lossy().to_string()).collect();
+        let env = Env::new(Some(init_builtin()));
+        run_in(Arc::clone(&env), "seen = {rows: [],}");
+        let body = ExprsParser::new().parse("
+            match $0 { 'stop' => break, _ => seen.rows = seen.rows + [[NR, FNR, NF, $1, $2]] }
+        ").unwrap();
+        let mode = LineMode { separator: FieldSeparator::parse(" ").unwrap(), record_separator: RecordSeparator::Newline, print: false };
+        assert!(matches!(*run_records(Arc::clone(&env), body, files, &mode), RuntimeValue::None));
+        assert_eq!(
+            run_in(env, "seen.rows").to_string(),
+            "[[1, 1, 2, 'x', '1'], [2, 2, 0, '', ''], [3, 3, 3, 'y', '2'], [4, 1, 1, 'w:3', '']]"
+        );
+
+        let split = |separator: &str, record: &str| FieldSeparator::parse(separator).unwrap().split(record).join("|");
+        assert_eq!(split(":", "a::b"), "a||b");
+        assert_eq!(split("\\t", "a\tb c"), "a|b c");
+        assert_eq!(split("/,\\s*/", "a, b,c"), "a|b|c");
+        assert!(FieldSeparator::parse("/(/").is_err());
+    }
+
+    #[test]
+    fn sections() {
+        let program = |code: &str| ProgramParser::new().parse(code).unwrap();
+        let env = Env::new(Some(init_builtin()));
+        let result = run_program(Arc::clone(&env), program("END { order + [3] }; BEGIN { order = [1] }; order = order + [2]"));
+        assert_eq!(result.to_string(), "[1, 2, 3]");
+        let result = run_program(Env::new(Some(init_builtin())), program("BEGIN { read['/no/such/file'] }; END { 1 }"));
+        assert!(matches!(*result, RuntimeValue::Error(_)));
+
+        let dir = temp_dir("sections");
+        std::fs::write(dir.join("a.txt"), "a b\nc\nstop\nd e f\n").unwrap();
+        let files = vec![dir.join("a.txt").to_string_lossy().to_string()];
+        let env = Env::new(Some(init_builtin()));
+        // assignments in the body land in the program env, while $0 and NR stay per record
+        let ast = program("BEGIN { fields = 0 }; match $0 { 'stop' => break, _ => fields = fields + NF }; END { last = NR; fields }");
+        let mode = LineMode { separator: FieldSeparator::parse(" ").unwrap(), record_separator: RecordSeparator::Newline, print: false };
+        // END's value is the result, as it is without line mode
+        assert!(matches!(*run_records(Arc::clone(&env), ast, files, &mode), RuntimeValue::Int64(3)));
+        assert!(matches!(run_in(env, "last"), RuntimeValue::Int64(3)));
+    }
+
+    #[test]
+    fn record_separators() {
+        let split = |separator: &str, input: &'static str| {
+            let records = Records::new(std::io::Cursor::new(input), RecordSeparator::parse(separator).unwrap());
+            records.map(|r| r.unwrap()).collect::<Vec<_>>().join("|")
+        };
+        assert_eq!(split("\\n", "a\r\nb\n\nc"), "a|b||c");
+        assert_eq!(split("", "\n\na\nb\n\n\n\nc\n\n"), "a\nb|c");
+        assert_eq!(split(";", "a;b;;c\n"), "a|b||c");
+        assert_eq!(split("/\\n?-+\\n/", "a\n---\nb\n-\nc"), "a|b|c");
+        assert!(RecordSeparator::parse("/(/").is_err());
+
+        assert_eq!(
+            run("records[['Error: x', '  at f', '  at g', 'ok', 'Error: y']] >- list").to_string(),
+            "['Error: x\n  at f\n  at g', 'ok', 'Error: y']"
+        );
+        assert_eq!(
+            run("records[['{', '\"a\": 1', '}', '{', '}'], start=/^[{]/] >- list").to_string(),
+            "['{\n\"a\": 1\n}', '{\n}']"
+        );
+        assert!(matches!(run("records[[1], start='x']"), RuntimeValue::Error(_)));
+
+        let dir = temp_dir("record_separators");
+        std::fs::write(dir.join("people.txt"), "name ann\nage 31\n\nname bob\nage 42\n").unwrap();
+        let files = vec![dir.join("people.txt").to_string_lossy().to_string()];
+        let env = Env::new(Some(init_builtin()));
+        let ast = ProgramParser::new().parse("BEGIN { RS = ''; seen = {rows: [],} }; seen.rows = seen.rows + [[NF, $2, $4]]").unwrap();
+        let mode = LineMode { separator: FieldSeparator::parse(":").unwrap(), record_separator: RecordSeparator::Newline, print: false };
+        assert!(matches!(*run_records(Arc::clone(&env), ast, files, &mode), RuntimeValue::None));
+        assert_eq!(run_in(env, "seen.rows").to_string(), "[[2, 'age 31', ''], [2, 'age 42', '']]");
+    }
+
+    #[test]
+    fn json() {
+        let env = Env::new(Some(init_builtin()));
+        run_in(Arc::clone(&env), r#"v = json.parse['{"a": [1, 2.5, null, true], "big": 123456789012345678901, "o": {"s": "x"}}']"#);
+        assert_eq!(run_in(Arc::clone(&env), "[v.a, v.big.type[], v.a.1.type[], v.o.s]").to_string(), "[[1, 2.5, none, true], 'i128', 'float', 'x']");
+        assert_eq!(run_in(Arc::clone(&env), "json.dump[v]").to_string(), r#"{"a":[1,2.5,null,true],"big":123456789012345678901,"o":{"s":"x"}}"#);
+        assert_eq!(run("json.dump[{b: [1], a: 'x'}, indent=1]").to_string(), "{\n \"a\": \"x\",\n \"b\": [\n  1\n ]\n}");
+        assert_eq!(
+            run("json.dump['x', indent=100000000000]").to_string(),
+            "value error: `indent` must be from 0 to 64 spaces, not 100000000000"
+        );
+        assert_eq!(run("[json.parse['null'] == none, none != json.parse['null'], json.parse['0'] == none]").to_string(), "[true, false, false]");
+        assert_eq!(
+            run("json.parse['{\"a\": 1,\n\n  \"b\": }']").to_string(),
+            "value error: bad json at line 3, column 8: expected value"
+        );
+        assert!(matches!(run("json.dump[f[x]{x}]"), RuntimeValue::Error(_)));
+        assert!(matches!(run("json.dump[json.parse['1e400']]"), RuntimeValue::Error(_)));
+
+        let lines = to_runtime_iter(["{\"n\": 1}", "", "{\"n\": 2}", "{\"n\": }"].into_iter().map(|s| Ok(RuntimeValue::String(Box::new(s.to_string())))));
+        set_name_from_env(Arc::clone(&env), "lines".to_string(), lines);
+        assert_eq!(run_in(Arc::clone(&env), "json.parse_lines[lines] -< {i.n} >- list").to_string(), "value error: bad json at line 4, column 7: expected value");
+        assert_eq!(run("json.dump_lines[[1, {x: 'y'}]] >- list").to_string(), r#"['1', '{"x":"y"}']"#);
+        assert_eq!(run("h = json.parse['0.5']; [h + 1, 3 / 2, json.parse['2.0'] == 2, 1 > h, -h]").to_string(), "[1.5, 1, true, true, -0.5]");
+    }
+
+    #[test]
+    fn csv() {
+        let dir = temp_dir("csv");
+        std::fs::write(dir.join("a.csv"), "name,note,n\nann,\"says \"\"hi\"\", twice\",1\nbob,\"two\nlines\",2\ncat\n").unwrap();
+        let env = Env::new(Some(init_builtin()));
+        set_name_from_env(Arc::clone(&env), "path".to_string(), RuntimeValue::String(Box::new(dir.join("a.csv").to_string_lossy().to_string())));
+        assert_eq!(
+            run_in(Arc::clone(&env), "csv[path] -< {[i.name, i.note, i.n]} >- list").to_string(),
+            "[['ann', 'says \"hi\", twice', '1'], ['bob', 'two\nlines', '2'], ['cat', '', '']]"
+        );
+        assert_eq!(
+            run_in(Arc::clone(&env), "csv_lines[csv[path], columns=['n', 'note']] >- list").to_string(),
+            "['n,note', '1,\"says \"\"hi\"\", twice\"', '2,\"two\nlines\"', ',']"
+        );
+        assert_eq!(run("csv[['a;b', '1;\"2;3\"'], sep=';', header=false] >- list").to_string(), "[['a', 'b'], ['1', '2;3']]");
+        assert_eq!(run("csv_lines[[[1, 'x'], ['y', 2]], sep='\\t'] >- list").to_string(), "['1\tx', 'y\t2']");
+        assert!(matches!(run("csv_lines[[{a: 1}]] >- list"), RuntimeValue::Error(_)));
+        assert_eq!(run("csv_lines[[{a: 1}], columns=['a', 'print']] >- list").to_string(), "['a,print', '1,']");
+        assert!(matches!(run("csv['/no/such/file.csv']"), RuntimeValue::Error(_)));
+    }
+
+    #[test]
+    fn typed_arguments() {
+        assert_eq!(run("[1, 2, 3].iter[].take['x']").to_string(), "type error: `n`: expected a non-negative int, not 'x'");
+        assert_eq!(run("[1, 2, 3].iter[].chunks[0]").to_string(), "value error: `n` must be positive");
+        assert_eq!(run("read[1]").to_string(), "type error: `path`: expected a str, not 1");
+        assert_eq!(run("try[f[]{[1].iter[].skip[-1]}, f[e]{e.kind}]").to_string(), "type");
+    }
+
+    #[test]
+    fn bytecode_matches_tree_walker() {
+        let corpus = [
+            "", "1", "'a' + 'b'", "x = 1; x = x + 1; x", "_ = 5", "2 * 3 - 4 / 2", "-(1 + 2)", "!0",
+            "1 / 0", "y", "$3", "[1, 'a', [2.str[]], none]", "[1, *[2, 3], 4]", "{a: 1, b: {c: [2]},}.b.c",
+            "o = {n: 1,}; o.n = o.n + 1; o", "[a, [b, *c]] = [1, [2, 3, 4]]; [a, b, c]", "{x, y: [p]} = {x: 1, y: [2]}; x + p",
+            "1 == 1 && 'a' != 'b'", "[1, 2] < [1, 3] || x", "0 || '' || 'last'", "1 && 0 && y", "1 < 'a'",
+            "g = f[a, b]{a * b}; g[2, b=5]", "g = f[a]{a}; g[1, 2]", "g = f[]{break; 1}; [g[]]", "f[x]{x}[a=1]",
+            "add = f[x]{f[y]{x + y}}; add[1][2]", "fib = f[n]{ n < 2 && n || fib[n - 1] + fib[n - 2] }; fib[10]",
+            "[1, 2, 3] -< f[i]{i * 10} >- list", "[1, 2, 3] -< {i + 1} >- list", "([1, 2, 3] -< f[v, k]{[k, v]}).last[]",
+            "s = {t: 0,}; [1, 2, 3] >- {s.t = s.t + i}; s.t", "1 -< {i}", "[1, 2] -< 3", "[1, 2, 3] -< {i > 1 && break || i} >- list",
+            "[4, 5].1", "xs = [4, 5]; xs.(xs.len[] - 1)", "[4, 5].x", "{a: 1,}.b", "{a: 1,}.?b", "'abc'.repr[]", "[1, 'a-b'].str[]",
+            "match [1, 2] { [a, b] => a + b, _ => 0 }", "match 3 { n: i64 if n > 2 => 'big', _ => 'small' }",
+            "type P { init = f[self, x]{ self.x = x }; get = f[self]{ self.x } }; P[7].get[]",
+            "type A { v = f[self]{ 1 } }; type B(A) { v = f[self]{ super.v[] + 1 } }; B[].v[]",
+            "type N { init = f[self, n]{ self.n = n }; add = f[self, o]{ N[self.n + o.n] }; eq = f[self, o]{ self.n == o.n } }; N[1] + N[2] == N[3]",
+            "try[f[]{read['/no/such/file']}, f[e]{e.kind}]", "read['/no/such/file']; 1", "[read['/no/such/file']]",
+            "json.dump[{b: [1], a: 'x'}]", "[1, 2, 3].iter[].filter[f[x]{x > 1}].sum[]",
+            "xs = [4, 5]; xs.(9)", "[4].('a')", "1.(0)", "{a: 1,}.1", "xs = [4, 5]; xs.(y)",
+            "o = {a: {b: 1,},}; o.a.b = o.a.b + 1; o.a.b", "n = 1; n.x = 2", "z.x = 1", "{a: 1,}.a.b = 2", "[a, b] = [1]",
+        ];
+        for code in corpus {
+            assert_eq!(run_compiled(code), walk(code), "{code}");
+        }
+    }
+}
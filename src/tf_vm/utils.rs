@@ -2,6 +2,7 @@ use std::sync::{Arc, RwLock};
 use crate::{Env, Expr};
 use crate::tf_vm::runtimes::RuntimeValue;
 
+#[allow(dead_code)]
 pub fn get_var_from_env(env: Arc<RwLock<Env>>, variable: Expr) -> Option<RuntimeValue> {
     if let Expr::Variable(var_name) = variable {
         env.read().unwrap().get(*var_name)
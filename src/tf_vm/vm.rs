@@ -1,114 +1,373 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
-use crate::ast::{Value, Op};
+use crate::ast::{Value, Op, Control};
 use crate::Expr;
+use crate::tf_vm::bytecode::run;
 use crate::tf_vm::env::Env;
-use crate::tf_vm::runtimes::{BuiltinOrExpr, RuntimeValue};
-use crate::tf_vm::utils::{get_name_from_env, get_self_from_env, set_name_from_env};
+use crate::tf_vm::error::RuntimeError;
+use crate::tf_vm::call::CallContext;
+use crate::tf_vm::iter::{to_runtime_iter, to_value, RuntimeIter};
+use crate::tf_vm::limits;
+use crate::tf_vm::modules::import;
+use regex::Regex;
+use crate::tf_vm::builtins::{get_repr_method, get_str_method, get_type_method};
+use crate::tf_vm::runtimes::{get_value_type_name, BuiltinOrExpr, RuntimeType, RuntimeValue};
+use crate::tf_vm::utils::{get_name_from_env, get_self_from_env};
 use crate::utils::b;
 
-pub struct VM;
-
-fn get_from_vec(v: &Vec<Box<RuntimeValue>>, value: &RuntimeValue) -> Box<RuntimeValue> {
-    match value {
-        RuntimeValue::Int64(k) => {
-            v[k.clone() as usize].clone()
-        }
-        RuntimeValue::Int128(k) => {
-            v[k.clone() as usize].clone()
+// end-of-stream and errors short-circuit any expression they show up in, so scripts never hold them as values
+macro_rules! propagate {
+    ($value:expr) => {{
+        let value = $value;
+        if let RuntimeValue::EOF | RuntimeValue::Error(_) = *value {
+            return value;
         }
-        _ => panic!("can't get from {v:?}")
+        value
+    }};
+}
+
+// a program's mistakes are errors it can catch with `try`, not panics
+pub fn raise(e: RuntimeError) -> Box<RuntimeValue> {
+    b(RuntimeValue::Error(e))
+}
+
+fn get_from_vec(v: &[Box<RuntimeValue>], value: &RuntimeValue) -> Box<RuntimeValue> {
+    let index = match value {
+        RuntimeValue::Int64(_) | RuntimeValue::Int128(_) => as_int(value).unwrap(),
+        _ => return raise(RuntimeError::Type(format!("a list index must be an int, not {}", value.repr())))
+    };
+    match usize::try_from(index).ok().and_then(|i| v.get(i)) {
+        Some(value) => value.clone(),
+        None => raise(RuntimeError::Value(format!("index {index} is out of range for a list of {}", v.len())))
     }
 }
 
-fn remove_code_pos(expr: Box<Expr>) -> Box<Expr> {
+pub fn remove_code_pos(expr: Box<Expr>) -> Box<Expr> {
     match *expr {
         Expr::ExprWithCodePos { exp, start: _, end: _ } => remove_code_pos(exp),
         _ => expr,
     }
 }
 
+// the same, for an expression that's only looked at
+pub fn without_code_pos(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::ExprWithCodePos { exp, start: _, end: _ } => without_code_pos(exp),
+        _ => expr,
+    }
+}
+
 pub fn runtime_get(env: Arc<RwLock<Env>>, is_expr: bool, from: Box<RuntimeValue>, key: Box<Expr>, weak: bool) -> Box<RuntimeValue> {
-    if is_expr {
-        let key = eval(Arc::clone(&env), vec![key]);
-        match *from {
-            RuntimeValue::List(v) => {
-                get_from_vec(&v, key.as_ref())
+    match (is_expr, *remove_code_pos(key)) {
+        (false, Expr::Variable(variable)) => runtime_get_field(env, from, &variable, weak),
+        (true, key) | (false, key @ Expr::Value(_)) => {
+            let key = propagate!(eval(Arc::clone(&env), vec![b(key)]));
+            runtime_index(*from, &key, is_expr)
+        }
+        (false, _) => raise(RuntimeError::Type(format!("can only get a field or a list index, not from {}", from.repr())))
+    }
+}
+
+// `xs.(i)`, or `xs.1` with a literal index
+pub fn runtime_index(from: RuntimeValue, key: &RuntimeValue, is_expr: bool) -> Box<RuntimeValue> {
+    match from {
+        RuntimeValue::List(v) => get_from_vec(&v, key),
+        from if is_expr => raise(RuntimeError::Type(format!("can't index {} with {}", from.repr(), key.repr()))),
+        from => raise(RuntimeError::Type(format!("can only get a field or a list index, not from {}", from.repr())))
+    }
+}
+
+// `from.name`, an object's own field or a method from the type of anything else; methods come bound to `from`
+pub fn runtime_get_field(env: Arc<RwLock<Env>>, from: Box<RuntimeValue>, name: &str, weak: bool) -> Box<RuntimeValue> {
+    let value = match from.as_ref() {
+        RuntimeValue::WithEnv { env, value: _ } => env.read().unwrap().lookup(name),
+        _ => from.get_type(env).get_env().read().unwrap().lookup(name),
+    };
+    let value = match value {
+        Some(value) => value,
+        None if weak => RuntimeValue::None,
+        None => return raise(RuntimeError::Name(format!("{} has no field `{name}`", from.repr()))),
+    };
+    match &value {
+        RuntimeValue::FuncDef { parameters: _, body: _, env } => b({
+            RuntimeValue::WithEnv {
+                env: Env::from(HashMap::from([
+                    ("self".to_string(), *from)
+                ]), Some(env.clone())),
+                value: b(value),
             }
-            _ => panic!("can't get from {from:?}, {key:?}")
+        }),
+        _ => b(value)
+    }
+}
+
+// overflowing an i128 or dividing by zero is an error the program can catch
+fn int_op2(op: &Op, x: i128, y: i128) -> Result<i128, RuntimeError> {
+    let value = match op {
+        Op::Add => x.checked_add(y),
+        Op::Sub => x.checked_sub(y),
+        Op::Mul => x.checked_mul(y),
+        Op::Div if y == 0 => return Err(RuntimeError::Value("division by zero".to_string())),
+        Op::Div => x.checked_div(y),
+        _ => unreachable!()
+    };
+    value.ok_or_else(|| RuntimeError::Value(format!("{x} {} {y} overflows", operator_symbol(op))))
+}
+
+fn operator_symbol(op: &Op) -> &'static str {
+    match op {
+        Op::Add => "+",
+        Op::Sub | Op::Neg => "-",
+        Op::Mul => "*",
+        Op::Div => "/",
+        Op::BAnd => "&",
+        Op::BOr => "|",
+        Op::BNot => "~",
+        Op::AsyncMap => "-<<",
+        _ => "?",
+    }
+}
+
+fn unsupported(op: &Op) -> Box<RuntimeValue> {
+    raise(RuntimeError::Type(format!("the `{}` operator isn't supported yet", operator_symbol(op))))
+}
+
+fn int_result(value: Result<i128, RuntimeError>) -> RuntimeValue {
+    match value {
+        Ok(value) => i64::try_from(value).map_or(RuntimeValue::Int128(value), RuntimeValue::Int64),
+        Err(e) => RuntimeValue::Error(e),
+    }
+}
+
+// how many elements `times` repeats of `len` make, if that can be built at all
+fn repeat_len(len: usize, times: usize) -> Result<usize, RuntimeError> {
+    len.checked_mul(times).filter(|n| *n <= isize::MAX as usize)
+        .ok_or_else(|| RuntimeError::Value(format!("can't repeat {len} elements {times} times")))
+}
+
+fn as_int(value: &RuntimeValue) -> Option<i128> {
+    match value {
+        RuntimeValue::Int64(i) => Some(i128::from(*i)),
+        RuntimeValue::Int128(i) => Some(*i),
+        _ => None
+    }
+}
+
+fn float_op2(op: &Op, x: f64, y: f64) -> f64 {
+    match op {
+        Op::Add => x + y,
+        Op::Sub => x - y,
+        Op::Mul => x * y,
+        Op::Div => x / y,
+        _ => unreachable!()
+    }
+}
+
+fn as_float(value: &RuntimeValue) -> Option<f64> {
+    match value {
+        RuntimeValue::Int64(i) => Some(*i as f64),
+        RuntimeValue::Int128(i) => Some(*i as f64),
+        RuntimeValue::Float(x) => Some(*x),
+        _ => None
+    }
+}
+
+pub fn builtin_op2(op: &Op, x: &RuntimeValue, y: &RuntimeValue) -> Option<RuntimeValue> {
+    use RuntimeValue::{Int64, Int128, Float, String, List};
+    Some(match (op, x, y) {
+        (_, Int64(_) | Int128(_), Int64(_) | Int128(_)) => int_result(int_op2(op, as_int(x)?, as_int(y)?)),
+        // an int with a float gives a float
+        (_, Int64(_) | Int128(_) | Float(_), Float(_)) | (_, Float(_), Int64(_) | Int128(_)) => {
+            Float(float_op2(op, as_float(x)?, as_float(y)?))
         }
-    } else {
-        match from.as_ref() {
-            RuntimeValue::WithEnv { env, value: _ } => {
-                match *key {
-                    Expr::Variable(variable) => {
-                        let scoped_value = get_name_from_env(
-                            env.clone(), *variable.clone()).unwrap_or_else(|| {
-                            if weak {
-                                RuntimeValue::None
-                            } else {
-                                panic!("can't get from")
-                            }
-                        });
-                        match &scoped_value {
-                            RuntimeValue::FuncDef { parameters: _, body: _, env } => b({
-                                RuntimeValue::WithEnv {
-                                    env: Env::from(HashMap::from([
-                                        ("self".to_string(), *from)
-                                    ]), Some(env.clone())),
-                                    value: b(scoped_value),
-                                }
-                            }),
-                            _ => b(scoped_value)
-                        }
-                    }
-                    _ => panic!("can only get token")
+        (Op::Add, String(x), String(y)) => String(b(x.to_string() + y.as_str())),
+        (Op::Add, List(x), List(y)) => List(x.iter().chain(y.iter()).cloned().collect()),
+        // sized before they're built, a repeat can ask for more than there is
+        (Op::Mul, String(s), n) | (Op::Mul, n, String(s)) => {
+            let times = usize::try_from(as_int(n)?).unwrap_or(0);
+            match repeat_len(s.len(), times) {
+                Ok(len) => {
+                    limits::check_size(len);
+                    String(b(s.repeat(times)))
                 }
+                Err(e) => RuntimeValue::Error(e),
             }
-            _ => {
-                let t = from.get_type(Arc::clone(&env));
-                match *key {
-                    Expr::Variable(v) => {
-                        let value = t.get_env().read().unwrap().get(*v).unwrap_or_else(|| {
-                            if weak {
-                                RuntimeValue::None
-                            } else {
-                                panic!("can't get from")
-                            }
-                        });
-                        match &value {
-                            RuntimeValue::FuncDef { parameters: _, body: _, env } => b({
-                                RuntimeValue::WithEnv {
-                                    env: Env::from(HashMap::from([
-                                        ("self".to_string(), *from)
-                                    ]), Some(env.clone())),
-                                    value: b(value),
-                                }
-                            }),
-                            _ => b(value)
-                        }
-                    }
-                    Expr::Value(value) => match *from {
-                        RuntimeValue::List(v) => {
-                            get_from_vec(
-                                &v,
-                                eval(Arc::clone(&env), vec![b(Expr::Value(value))]).as_ref(),
-                            )
-                        }
-                        _ => panic!("can't get {value:?} from {from:?}")
+        }
+        (Op::Mul, List(list), n) | (Op::Mul, n, List(list)) => {
+            let times = usize::try_from(as_int(n)?).unwrap_or(0);
+            match repeat_len(list.len(), times) {
+                Ok(len) => {
+                    limits::check_size(len.saturating_mul(8));
+                    List(list.iter().cloned().cycle().take(len).collect())
+                }
+                Err(e) => RuntimeValue::Error(e),
+            }
+        }
+        _ => return None
+    })
+}
+
+fn operator_method_name(op: &Op) -> &'static str {
+    match op {
+        Op::Add => "add",
+        Op::Sub => "sub",
+        Op::Mul => "mul",
+        Op::Div => "div",
+        Op::Eq => "eq",
+        Op::Ne => "ne",
+        Op::Gt => "gt",
+        Op::Ge => "ge",
+        Op::Lt => "lt",
+        Op::Le => "le",
+        _ => panic!("{op:?} can't be overloaded")
+    }
+}
+
+// calls the operator's method on `x`, from its own env for objects or from its type env otherwise
+pub fn runtime_operator(env: Arc<RwLock<Env>>, op: &Op, x: Box<RuntimeValue>, y: RuntimeValue) -> Option<Box<RuntimeValue>> {
+    let name = operator_method_name(op).to_string();
+    let found = match x.as_ref() {
+        RuntimeValue::WithEnv { env, value: _ } => get_name_from_env(Arc::clone(env), name.clone()).is_some(),
+        _ => get_name_from_env(Arc::clone(&env), get_value_type_name(&x))
+            .is_some_and(|_| x.get_type(Arc::clone(&env)).get_env().read().unwrap().get(name.clone()).is_some()),
+    };
+    if !found {
+        return None;
+    }
+    let method = runtime_get_field(Arc::clone(&env), x, &name, false);
+    Some(runtime_func_apply(env, method, vec![y]))
+}
+
+// a bound method already has `self`, so the arguments start after it
+fn skip_bound_self(parameters: &mut Vec<Box<String>>, sub_env: &Arc<RwLock<Env>>) {
+    if parameters.first().is_some_and(|p| p.as_str() == "self") && get_self_from_env(sub_env.clone()).is_some() {
+        parameters.remove(0);
+    }
+}
+
+fn not_callable(func: &RuntimeValue) -> RuntimeError {
+    RuntimeError::Type(format!("can't call {}, it's not a function", func.repr()))
+}
+
+fn too_many_arguments(parameters: usize, given: usize) -> RuntimeError {
+    RuntimeError::Type(format!("the function takes {parameters} arguments, but {given} were given"))
+}
+
+fn func_parameters(func: &RuntimeValue) -> Result<Vec<Box<String>>, RuntimeError> {
+    match func {
+        RuntimeValue::FuncDef { parameters, body: _, env: _ } => Ok(parameters.clone()),
+        RuntimeValue::WithEnv { value, env: sub_env } => match value.as_ref() {
+            RuntimeValue::FuncDef { parameters, body: _, env: _ } => {
+                let mut parameters = parameters.clone();
+                skip_bound_self(&mut parameters, sub_env);
+                Ok(parameters)
+            }
+            _ => Err(not_callable(func))
+        },
+        RuntimeValue::RuntimeType(t @ RuntimeType::Custom { name: _, parent: _, env: _ }) => {
+            match get_name_from_env(t.get_env(), "init".to_string()) {
+                Some(init) => {
+                    let mut parameters = func_parameters(&init)?;
+                    if parameters.first().is_some_and(|p| p.as_str() == "self") {
+                        parameters.remove(0);
                     }
-                    _ => panic!("only can get variable or value from type {t:?}, not {key:?}")
+                    Ok(parameters)
                 }
+                None => Ok(vec![])
             }
         }
+        _ => Err(not_callable(func))
+    }
+}
+
+// like runtime_func_call, but with already evaluated positional arguments
+pub fn runtime_func_apply(env: Arc<RwLock<Env>>, func: Box<RuntimeValue>, values: Vec<RuntimeValue>) -> Box<RuntimeValue> {
+    let parameters = match func_parameters(&func) {
+        Ok(parameters) => parameters,
+        Err(e) => return raise(e),
+    };
+    if values.len() > parameters.len() {
+        return raise(too_many_arguments(parameters.len(), values.len()));
+    }
+    let external_variables = parameters.into_iter().zip(values).map(|(p, v)| (*p, v)).collect();
+    runtime_func_call(env, func, vec![], external_variables)
+}
+
+// the function on the right of `-<` or `>-`, where a bare block is a function of the implicit `i`
+fn stream_func(env: Arc<RwLock<Env>>, expr: Box<Expr>) -> Box<RuntimeValue> {
+    match *remove_code_pos(expr) {
+        Expr::Block(block) => b(RuntimeValue::FuncDef {
+            parameters: vec![],
+            body: BuiltinOrExpr::Expr(b(Expr::Block(block))),
+            env: Env::new(Some(env)),
+        }),
+        expr => eval(env, vec![b(expr)]),
+    }
+}
+
+// an element goes to the first parameter and its index to the second, or to `i` without parameters
+fn stream_call(env: Arc<RwLock<Env>>, func: &RuntimeValue, arity: usize, index: usize, value: RuntimeValue) -> Box<RuntimeValue> {
+    if arity == 0 {
+        runtime_func_call(env, b(func.clone()), vec![], HashMap::from([("i".to_string(), value)]))
+    } else {
+        let mut values = vec![value, RuntimeValue::Int64(index as i64)];
+        values.truncate(arity);
+        runtime_func_apply(env, b(func.clone()), values)
+    }
+}
+
+// an argument by position, or by name for `name=value`
+pub type Argument = (Option<String>, RuntimeValue);
+
+// in order, up to the first that gives EOF or an error, which is returned instead
+fn eval_arguments(env: &Arc<RwLock<Env>>, arguments: Vec<Box<Expr>>) -> Result<Vec<Argument>, Box<RuntimeValue>> {
+    let mut values = Vec::with_capacity(arguments.len());
+    for argument in arguments {
+        let (name, value) = match *remove_code_pos(argument) {
+            Expr::Op2 { op: Op::Assign, x, y } => match *remove_code_pos(x) {
+                Expr::Variable(variable) => (Some(*variable), eval(Arc::clone(env), vec![y])),
+                _ => return Err(raise(RuntimeError::Type("only a name can be given a named argument".to_string())))
+            },
+            argument => (None, eval(Arc::clone(env), vec![b(argument)])),
+        };
+        if let RuntimeValue::EOF | RuntimeValue::Error(_) = *value {
+            return Err(value);
+        }
+        values.push((name, *value));
     }
+    Ok(values)
 }
 
 pub fn runtime_func_call(
+    env: Arc<RwLock<Env>>,
     runtime_func_def: Box<RuntimeValue>,
     arguments: Vec<Box<Expr>>,
     external_variables: HashMap<String, RuntimeValue>,
 ) -> Box<RuntimeValue> {
+    match eval_arguments(&env, arguments) {
+        Ok(arguments) => runtime_call(env, runtime_func_def, arguments, external_variables),
+        Err(value) => value,
+    }
+}
+
+// what a call expression gives its caller: an ended stream is `none` there,
+// so an EOF only ends the `next` or mapping function that produced it
+pub fn call_result(value: Box<RuntimeValue>) -> Box<RuntimeValue> {
+    match *value {
+        RuntimeValue::EOF => b(RuntimeValue::None),
+        _ => value,
+    }
+}
+
+// like runtime_func_call, with the arguments already evaluated
+pub fn runtime_call(
+    env: Arc<RwLock<Env>>,
+    runtime_func_def: Box<RuntimeValue>,
+    arguments: Vec<Argument>,
+    external_variables: HashMap<String, RuntimeValue>,
+) -> Box<RuntimeValue> {
+    let _call = limits::enter_call();
     let (parameters, func_body, func_env) = match *runtime_func_def {
         RuntimeValue::FuncDef {
             parameters,
@@ -119,33 +378,487 @@ pub fn runtime_func_call(
             value,
             env: sub_env
         } => match *value {
-            RuntimeValue::FuncDef { parameters, body, env: _ } => {
+            RuntimeValue::FuncDef { mut parameters, body, env: _ } => {
+                skip_bound_self(&mut parameters, &sub_env);
                 (parameters, body, sub_env)
             }
-            _ => panic!("can't call {value:?}, it's not a function")
+            value => return raise(not_callable(&RuntimeValue::WithEnv { value: b(value), env: sub_env }))
         },
-        _ => panic!("can't call {runtime_func_def:?}, it's not a function")
+        RuntimeValue::RuntimeType(t @ RuntimeType::Custom { name: _, parent: _, env: _ }) => {
+            let instance = b(RuntimeValue::WithEnv {
+                env: Env::new(Some(t.get_env())),
+                value: b(RuntimeValue::RuntimeType(t.clone())),
+            });
+            if get_name_from_env(t.get_env(), "init".to_string()).is_some() {
+                let init = runtime_get_field(Arc::clone(&env), instance.clone(), "init", false);
+                if let e @ RuntimeValue::Error(_) = *runtime_call(env, init, arguments, external_variables) {
+                    return b(e);
+                }
+            } else if !arguments.is_empty() {
+                return raise(too_many_arguments(0, arguments.len()));
+            }
+            return instance;
+        }
+        _ => return raise(not_callable(&runtime_func_def))
     };
+    let given = arguments.len();
     let func_run_env = Env::from(external_variables, Some(func_env));
-    arguments.into_iter().enumerate().for_each(|(i, arguments)| {
-        let argument = *eval(func_run_env.clone(), vec![arguments.clone()]);
-        match *remove_code_pos(arguments) {
-            Expr::Op2 { op, x, y } => match op {
-                Op::Assign => match *remove_code_pos(x) {
-                    Expr::Variable(variable) => func_run_env.write().unwrap().set(*variable, *eval(func_run_env.clone(), vec![y])),
-                    _ => panic!("Assign can't be here")
-                },
-                _ => func_run_env.write().unwrap().set(*parameters[i].clone(), argument),
-            },
-            _ => func_run_env.write().unwrap().set(*parameters[i].clone(), argument),
-        }
-    });
+    for (i, (name, value)) in arguments.into_iter().enumerate() {
+        let name = match name {
+            Some(name) => name,
+            None => match parameters.get(i) {
+                Some(parameter) => *parameter.clone(),
+                None => return raise(too_many_arguments(parameters.len(), given)),
+            }
+        };
+        func_run_env.write().unwrap().set(name, value)
+    }
     match func_body {
         BuiltinOrExpr::Expr(expr) => {
             eval(func_run_env, vec![expr])
         }
+        BuiltinOrExpr::Code(code) => {
+            run(func_run_env, &code)
+        }
         BuiltinOrExpr::Builtin(builtin) => {
-            b(builtin(func_run_env))
+            let value = to_value(builtin(&CallContext::new(func_run_env)));
+            limits::check_value(&value);
+            b(value)
+        }
+    }
+}
+
+// `from.name = value`, gives none or the error
+pub fn runtime_set_field(from: RuntimeValue, name: Option<&str>, value: RuntimeValue) -> Box<RuntimeValue> {
+    match (from, name) {
+        (RuntimeValue::WithEnv { env, value: _ }, Some(name)) => env.write().unwrap().set(name.to_string(), value),
+        (from, _) => return raise(RuntimeError::Type(format!("can only set a field of an object, not of {}", from.repr())))
+    }
+    b(RuntimeValue::None)
+}
+
+// gives none, or the error that stopped the assignment
+pub fn runtime_assign(env: Arc<RwLock<Env>>, target: &Expr, value: RuntimeValue) -> Box<RuntimeValue> {
+    match without_code_pos(target) {
+        Expr::Variable(name) => if name.as_str() != "_" {
+            env.write().unwrap().set(name.to_string(), value)
+        },
+        Expr::Get { from, key, is_expr: false, weak: _ } => {
+            let from = propagate!(eval(Arc::clone(&env), vec![from.clone()]));
+            let name = match without_code_pos(key) {
+                Expr::Variable(name) => Some(name.as_str()),
+                _ => None,
+            };
+            return runtime_set_field(*from, name, value);
+        }
+        // [a, b, *rest] = xs
+        Expr::List(patterns) => {
+            let values = match value {
+                RuntimeValue::List(values) => values,
+                value => return raise(RuntimeError::Type(format!("can't destructure {}, it's not a list", value.repr())))
+            };
+            let (fixed, has_unpack) = match count_unpack(patterns) {
+                Ok(count) => count,
+                Err(e) => return raise(e),
+            };
+            if values.len() < fixed || (!has_unpack && values.len() > fixed) {
+                return raise(RuntimeError::Value(format!(
+                    "can't destructure {} values into {}{fixed} names",
+                    values.len(),
+                    if has_unpack { "at least " } else { "" }
+                )));
+            }
+            let rest_len = values.len() - fixed;
+            let mut values = values.into_iter();
+            for pattern in patterns {
+                propagate!(match without_code_pos(pattern) {
+                    Expr::Unpack(rest) => runtime_assign(
+                        Arc::clone(&env),
+                        rest,
+                        RuntimeValue::List(values.by_ref().take(rest_len).collect()),
+                    ),
+                    pattern => runtime_assign(Arc::clone(&env), pattern, *values.next().unwrap()),
+                });
+            }
+        }
+        // {a, b: [c, d]} = o
+        Expr::Object(fields) => {
+            let object_env = match value {
+                RuntimeValue::WithEnv { env, value: _ } => env,
+                value => return raise(RuntimeError::Type(format!("can't destructure {}, it's not an object", value.repr())))
+            };
+            for (key, pattern) in fields {
+                // an object's own fields, not the builtins its env inherits
+                let field = object_env.read().unwrap().get_own(key);
+                match field {
+                    Some(field) => propagate!(runtime_assign(Arc::clone(&env), pattern, field)),
+                    None => return raise(RuntimeError::Name(format!("can't destructure, the object has no field `{key}`"))),
+                };
+            }
+        }
+        _ => return raise(RuntimeError::Type("can only assign to a name, a field or a pattern".to_string()))
+    }
+    b(RuntimeValue::None)
+}
+
+pub fn runtime_eq(x: &RuntimeValue, y: &RuntimeValue) -> bool {
+    match (x, y) {
+        (RuntimeValue::Bool(x), RuntimeValue::Bool(y)) => x == y,
+        (RuntimeValue::Int64(x), RuntimeValue::Int64(y)) => x == y,
+        (RuntimeValue::Int64(x), RuntimeValue::Int128(y)) => i128::from(*x) == *y,
+        (RuntimeValue::Int128(x), RuntimeValue::Int64(y)) => *x == i128::from(*y),
+        (RuntimeValue::Int128(x), RuntimeValue::Int128(y)) => x == y,
+        (RuntimeValue::Float(_), _) | (_, RuntimeValue::Float(_)) => as_float(x).zip(as_float(y)).is_some_and(|(x, y)| x == y),
+        (RuntimeValue::String(x), RuntimeValue::String(y)) => x == y,
+        (RuntimeValue::Regex(x), RuntimeValue::Regex(y)) => x == y,
+        (RuntimeValue::List(x), RuntimeValue::List(y)) => {
+            x.len() == y.len() && x.iter().zip(y.iter()).all(|(x, y)| runtime_eq(x, y))
+        }
+        (RuntimeValue::None, RuntimeValue::None) => true,
+        // the name `none` is the type, but it's how a program writes the value too, as in `x == none`
+        (RuntimeValue::None, RuntimeValue::RuntimeType(RuntimeType::None { env: _ })) |
+        (RuntimeValue::RuntimeType(RuntimeType::None { env: _ }), RuntimeValue::None) => true,
+        (RuntimeValue::WithEnv { env: x, value: _ }, RuntimeValue::WithEnv { env: y, value: _ }) => Arc::ptr_eq(x, y),
+        _ => false
+    }
+}
+
+pub fn runtime_cmp(x: &RuntimeValue, y: &RuntimeValue) -> Option<Ordering> {
+    match (x, y) {
+        (RuntimeValue::Int64(x), RuntimeValue::Int64(y)) => Some(x.cmp(y)),
+        (RuntimeValue::Int64(x), RuntimeValue::Int128(y)) => Some(i128::from(*x).cmp(y)),
+        (RuntimeValue::Int128(x), RuntimeValue::Int64(y)) => Some(x.cmp(&i128::from(*y))),
+        (RuntimeValue::Int128(x), RuntimeValue::Int128(y)) => Some(x.cmp(y)),
+        (RuntimeValue::Float(_), _) | (_, RuntimeValue::Float(_)) => as_float(x)?.partial_cmp(&as_float(y)?),
+        (RuntimeValue::String(x), RuntimeValue::String(y)) => Some(x.cmp(y)),
+        (RuntimeValue::List(x), RuntimeValue::List(y)) => {
+            for (x, y) in x.iter().zip(y.iter()) {
+                match runtime_cmp(x, y)? {
+                    Ordering::Equal => continue,
+                    ordering => return Some(ordering),
+                }
+            }
+            Some(x.len().cmp(&y.len()))
+        }
+        _ => None
+    }
+}
+
+// returns the patterns without code positions, how many of them are not `*rest` and whether one is
+fn split_unpack(patterns: Vec<Box<Expr>>) -> Result<(Vec<Box<Expr>>, usize, bool), RuntimeError> {
+    let (fixed, has_unpack) = count_unpack(&patterns)?;
+    Ok((patterns.into_iter().map(remove_code_pos).collect(), fixed, has_unpack))
+}
+
+// how many patterns take one value each, and whether a `*` takes the rest
+fn count_unpack(patterns: &[Box<Expr>]) -> Result<(usize, bool), RuntimeError> {
+    let unpack_count = patterns.iter().filter(|p| matches!(without_code_pos(p), Expr::Unpack(_))).count();
+    if unpack_count > 1 {
+        return Err(RuntimeError::Value("can't destructure with more than one `*` in a list pattern".to_string()));
+    }
+    Ok((patterns.len() - unpack_count, unpack_count == 1))
+}
+
+// an instance is of its own type and of every type that type inherits from
+fn is_of_type(value: &RuntimeValue, type_name: &str) -> bool {
+    if get_value_type_name(value) == type_name {
+        return true;
+    }
+    let mut t = match value {
+        RuntimeValue::WithEnv { value, env: _ } => match value.as_ref() {
+            RuntimeValue::RuntimeType(t) => t,
+            _ => return false
+        },
+        _ => return false
+    };
+    while let RuntimeType::Custom { name: _, parent: Some(parent), env: _ } = t {
+        if parent.name() == type_name {
+            return true;
+        }
+        t = parent;
+    }
+    false
+}
+
+// whether `value` fits `pattern`, or what stopped the match, e.g. an error in a value pattern
+pub fn runtime_match(
+    env: Arc<RwLock<Env>>,
+    pattern: Box<Expr>,
+    value: &RuntimeValue,
+    bindings: &mut HashMap<String, RuntimeValue>,
+) -> Result<bool, Box<RuntimeValue>> {
+    match *remove_code_pos(pattern) {
+        Expr::Variable(name) => {
+            if name.as_str() == "_" {
+                return Ok(true);
+            }
+            match get_name_from_env(Arc::clone(&env), *name.clone()) {
+                Some(RuntimeValue::RuntimeType(t)) => Ok(is_of_type(value, &t.name())),
+                _ => {
+                    bindings.insert(*name, value.clone());
+                    Ok(true)
+                }
+            }
+        }
+        Expr::Typed { name, type_name } => {
+            if !is_of_type(value, &type_name) {
+                return Ok(false);
+            }
+            if name.as_str() != "_" {
+                bindings.insert(*name, value.clone());
+            }
+            Ok(true)
+        }
+        Expr::Value(Value::Regex(regex)) => match value {
+            RuntimeValue::String(s) => {
+                let regex = Regex::new(regex.as_str())
+                    .map_err(|e| raise(RuntimeError::Value(format!("bad pattern /{regex}/: {e}"))))?;
+                match regex.captures(s.as_str()) {
+                    Some(captures) => {
+                        for name in regex.capture_names().flatten() {
+                            bindings.insert(name.to_string(), captures.name(name).map_or(
+                                RuntimeValue::None,
+                                |m| RuntimeValue::String(b(m.as_str().to_string())),
+                            ));
+                        }
+                        Ok(true)
+                    }
+                    None => Ok(false)
+                }
+            }
+            _ => Ok(false)
+        },
+        Expr::List(patterns) => match value {
+            RuntimeValue::List(values) => {
+                let (patterns, fixed, has_unpack) = split_unpack(patterns).map_err(raise)?;
+                if values.len() < fixed || (!has_unpack && values.len() > fixed) {
+                    return Ok(false);
+                }
+                let rest_len = values.len() - fixed;
+                let mut values = values.iter();
+                for pattern in patterns {
+                    let matched = match *pattern {
+                        Expr::Unpack(rest) => runtime_match(
+                            Arc::clone(&env),
+                            rest,
+                            &RuntimeValue::List(values.by_ref().take(rest_len).cloned().collect()),
+                            bindings,
+                        )?,
+                        pattern => runtime_match(Arc::clone(&env), b(pattern), values.next().unwrap(), bindings)?,
+                    };
+                    if !matched {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            _ => Ok(false)
+        },
+        Expr::Object(fields) => match value {
+            // only the object's own fields, not what its env inherits from the builtins
+            RuntimeValue::WithEnv { env: object_env, value: _ } => {
+                for (key, pattern) in fields {
+                    let field = object_env.read().unwrap().get_own(&key);
+                    match field {
+                        Some(field) if runtime_match(Arc::clone(&env), pattern, &field, bindings)? => {}
+                        _ => return Ok(false),
+                    }
+                }
+                Ok(true)
+            }
+            _ => Ok(false)
+        },
+        pattern => {
+            let expected = eval(env, vec![b(pattern)]);
+            match *expected {
+                RuntimeValue::EOF | RuntimeValue::Error(_) => Err(expected),
+                _ => Ok(runtime_eq(&expected, value)),
+            }
+        }
+    }
+}
+
+pub fn runtime_type_def(env: Arc<RwLock<Env>>, name: String, parent: Option<Box<Expr>>, body: Box<Expr>) -> Box<RuntimeValue> {
+    let parent = match parent.map(|parent| *eval(Arc::clone(&env), vec![parent])) {
+        None => None,
+        Some(RuntimeValue::RuntimeType(t @ RuntimeType::Custom { name: _, parent: _, env: _ })) => Some(t),
+        Some(e @ (RuntimeValue::EOF | RuntimeValue::Error(_))) => return b(e),
+        Some(value) => return raise(RuntimeError::Type(format!("type {name} can only inherit from a type, not {}", value.repr()))),
+    };
+    // the body runs in its own scope, so methods can still see the names around the type definition
+    let body_env = Env::from(HashMap::from([
+        ("super".to_string(), parent.clone().map_or(RuntimeValue::None, RuntimeValue::RuntimeType))
+    ]), Some(Arc::clone(&env)));
+    propagate!(eval(Arc::clone(&body_env), vec![body]));
+    let mut variables = body_env.read().unwrap().variables();
+    variables.remove("super");
+    // a subtype inherits these from its parent, so they'd only shadow the parent's own
+    if parent.is_none() {
+        let root = env.read().unwrap().root();
+        variables.entry("type".to_string()).or_insert_with(|| get_type_method(Arc::clone(&root)));
+        variables.entry("str".to_string()).or_insert_with(|| get_str_method(Arc::clone(&root)));
+        variables.entry("repr".to_string()).or_insert_with(|| get_repr_method(root));
+    }
+    let t = RuntimeValue::RuntimeType(RuntimeType::Custom {
+        name: b(name.clone()),
+        env: Env::from(variables, parent.as_ref().map(|p| p.get_env())),
+        parent: parent.map(b),
+    });
+    env.write().unwrap().set(name, t.clone());
+    b(t)
+}
+
+// as in awk, a field past the end of the record is empty
+fn missing_field(env: &Env, name: &str) -> Option<RuntimeValue> {
+    let is_field = name.strip_prefix('$').is_some_and(|n| !n.is_empty() && n.bytes().all(|c| c.is_ascii_digit()));
+    (is_field && env.get("$0".to_string()).is_some()).then(|| RuntimeValue::String(b(String::new())))
+}
+
+// for the error on what only has a meaning inside something else, e.g. `*rest` outside a list
+fn describe(expr: &Expr) -> &'static str {
+    match expr {
+        Expr::Unpack(_) => "`*` outside a list",
+        Expr::Typed { name: _, type_name: _ } => "a typed name outside a pattern",
+        Expr::Begin(_) | Expr::End(_) => "BEGIN or END inside an expression",
+        Expr::Control(Control::Ignore) => "`ignore`, it isn't supported yet",
+        _ => "this expression",
+    }
+}
+
+// what follows are the parts of evaluation both the tree walker and the bytecode interpreter share
+
+pub fn runtime_load(env: &Arc<RwLock<Env>>, name: &str) -> RuntimeValue {
+    let env = env.read().unwrap();
+    env.lookup(name).
+        or_else(|| missing_field(&env, name)).
+        unwrap_or_else(|| RuntimeValue::Error(match name.strip_prefix('#').and_then(|i| i.parse::<usize>().ok()) {
+            // the hidden name of a destructuring parameter, see FuncDef in the grammar
+            Some(i) => RuntimeError::Type(format!("argument {} is missing, the function destructures it", i + 1)),
+            None => RuntimeError::Name(format!("`{name}` is not defined")),
+        }))
+}
+
+pub fn runtime_object(env: &Arc<RwLock<Env>>, fields: Vec<(String, RuntimeValue)>) -> Box<RuntimeValue> {
+    let object_env = Env::new(Some(env.read().unwrap().root()));
+    for (key, value) in fields {
+        object_env.write().unwrap().set(key, value);
+    }
+    let object = RuntimeValue::WithEnv {
+        env: object_env,
+        value: b(RuntimeValue::None),
+    };
+    limits::check_value(&object);
+    b(object)
+}
+
+pub fn runtime_arithmetic(env: &Arc<RwLock<Env>>, op: &Op, x: Box<RuntimeValue>, y: Box<RuntimeValue>) -> Box<RuntimeValue> {
+    match builtin_op2(op, &x, &y) {
+        Some(value) => {
+            limits::check_value(&value);
+            b(value)
+        }
+        None => {
+            let message = format!("can't use {} on {} and {}", operator_symbol(op), x.repr(), y.repr());
+            runtime_operator(Arc::clone(env), op, x, *y).unwrap_or_else(|| raise(RuntimeError::Type(message)))
+        }
+    }
+}
+
+pub fn runtime_equality(env: &Arc<RwLock<Env>>, op: &Op, x: Box<RuntimeValue>, y: Box<RuntimeValue>) -> Box<RuntimeValue> {
+    let overloaded = match x.as_ref() {
+        RuntimeValue::WithEnv { env: _, value: _ } => {
+            runtime_operator(Arc::clone(env), op, x.clone(), *y.clone()).or_else(|| if *op == Op::Ne {
+                runtime_operator(Arc::clone(env), &Op::Eq, x.clone(), *y.clone())
+                    .map(|eq| b(RuntimeValue::Bool(!eq.is_truthy())))
+            } else {
+                None
+            })
+        }
+        _ => None
+    };
+    overloaded.unwrap_or_else(|| b(RuntimeValue::Bool(runtime_eq(&x, &y) == (*op == Op::Eq))))
+}
+
+pub fn runtime_comparison(env: &Arc<RwLock<Env>>, op: &Op, x: Box<RuntimeValue>, y: Box<RuntimeValue>) -> Box<RuntimeValue> {
+    match runtime_cmp(&x, &y) {
+        Some(ordering) => b(RuntimeValue::Bool(match op {
+            Op::Gt => ordering.is_gt(),
+            Op::Ge => ordering.is_ge(),
+            Op::Lt => ordering.is_lt(),
+            _ => ordering.is_le(),
+        })),
+        None => runtime_operator(Arc::clone(env), op, x.clone(), *y.clone())
+            .unwrap_or_else(|| raise(RuntimeError::Type(format!("can't compare {} and {}", x.repr(), y.repr()))))
+    }
+}
+
+pub fn runtime_op1(op: &Op, x: Box<RuntimeValue>) -> Box<RuntimeValue> {
+    match op {
+        Op::Not => b(RuntimeValue::Bool(!x.is_truthy())),
+        Op::Neg => match *x {
+            RuntimeValue::Int64(_) | RuntimeValue::Int128(_) => b(int_result(int_op2(&Op::Sub, 0, as_int(&x).unwrap()))),
+            RuntimeValue::Float(x) => b(RuntimeValue::Float(-x)),
+            x => raise(RuntimeError::Type(format!("can't negate {}", x.repr())))
+        },
+        op => unsupported(op)
+    }
+}
+
+// `source -< func`, lazily
+pub fn runtime_map(env: &Arc<RwLock<Env>>, source: RuntimeIter, func: RuntimeValue) -> Box<RuntimeValue> {
+    let arity = match func_parameters(&func) {
+        Ok(parameters) => parameters.len(),
+        Err(e) => return raise(e),
+    };
+    let env = Arc::clone(env);
+    // a mapping function that returns EOF (e.g. via `break`) ends the stream
+    b(to_runtime_iter(source.enumerate().map_while(move |(index, value)| match value {
+        Ok(value) => match *stream_call(Arc::clone(&env), &func, arity, index, value) {
+            RuntimeValue::EOF => None,
+            RuntimeValue::Error(e) => Some(Err(e)),
+            value => Some(Ok(value)),
+        },
+        Err(e) => Some(Err(e)),
+    }).fuse()))
+}
+
+// `source >- func`, right away
+pub fn runtime_collect(env: &Arc<RwLock<Env>>, source: RuntimeIter, func: RuntimeValue) -> Box<RuntimeValue> {
+    match func {
+        RuntimeValue::RuntimeType(RuntimeType::List { env: _ }) => {
+            let mut size = 0;
+            let mut values = vec![];
+            for value in source {
+                match value {
+                    Ok(value) => {
+                        size += limits::approx_size(&value) + 8;
+                        limits::check_size(size);
+                        values.push(b(value))
+                    }
+                    Err(e) => return b(RuntimeValue::Error(e)),
+                }
+            }
+            limits::charge(size);
+            b(RuntimeValue::List(values))
+        }
+        // any other sink is called once per element
+        func => {
+            let arity = match func_parameters(&func) {
+                Ok(parameters) => parameters.len(),
+                Err(e) => return raise(e),
+            };
+            for (index, value) in source.enumerate() {
+                let value = match value {
+                    Ok(value) => value,
+                    Err(e) => return b(RuntimeValue::Error(e)),
+                };
+                match *stream_call(Arc::clone(env), &func, arity, index, value) {
+                    RuntimeValue::EOF => break,
+                    e @ RuntimeValue::Error(_) => return b(e),
+                    _ => {}
+                }
+            }
+            b(RuntimeValue::None)
         }
     }
 }
@@ -153,196 +866,161 @@ pub fn runtime_func_call(
 pub fn eval(env: Arc<RwLock<Env>>, asts: Vec<Box<Expr>>) -> Box<RuntimeValue> {
     let mut last = b(RuntimeValue::None);
     for ast in asts {
+        limits::step();
         let ast = remove_code_pos(ast);
         last = match *ast {
             Expr::Block(block) => eval(Arc::clone(&env), block),
-            Expr::List(list) => b(RuntimeValue::List(
-                list.into_iter().map(|i| eval(Arc::clone(&env), vec![i])).collect()
-            )),
+            Expr::List(list) => {
+                let mut values = Vec::with_capacity(list.len());
+                for i in list {
+                    match *remove_code_pos(i) {
+                        Expr::Unpack(i) => match *propagate!(eval(Arc::clone(&env), vec![i])) {
+                            RuntimeValue::List(list) => values.extend(list),
+                            value => return raise(RuntimeError::Type(format!("can't unpack {}, it's not a list", value.repr())))
+                        },
+                        i => values.push(propagate!(eval(Arc::clone(&env), vec![b(i)]))),
+                    }
+                }
+                let list = RuntimeValue::List(values);
+                limits::check_value(&list);
+                b(list)
+            }
+            Expr::Object(fields) => {
+                let mut values = Vec::with_capacity(fields.len());
+                for (key, value) in fields {
+                    values.push((*key, *propagate!(eval(Arc::clone(&env), vec![value]))));
+                }
+                runtime_object(&env, values)
+            }
             Expr::Value(value) => match value {
                 Value::String(string) => b(RuntimeValue::String(string)),
                 Value::Int64(int64) => b(RuntimeValue::Int64(int64)),
                 Value::Int128(int128) => b(RuntimeValue::Int128(int128)),
                 Value::Regex(regex) => b(RuntimeValue::Regex(regex)),
             },
-            Expr::Variable(name) => {
-                let env = Arc::clone(&env);
-                let r_env = env.read().unwrap();
-                b(r_env.get(*name.clone()).
-                    unwrap_or_else(|| panic!("can't find variable or token `{name:?}`")))
-            }
+            Expr::Variable(name) => b(runtime_load(&env, &name)),
             Expr::Op2 { op, x, y } => match op {
                 Op::Assign => {
-                    let y = eval(Arc::clone(&env), vec![y]);
-                    let env = Arc::clone(&env);
-                    match *x {
-                        Expr::Variable(name) => env.write().unwrap().set(*name, *y),
-                        Expr::ExprWithCodePos { exp, start: _, end: _ } => match *exp {
-                            Expr::Variable(name) => env.write().unwrap().set(*name, *y),
-                            _ => panic!("assign not impl")
-                        },
-                        Expr::Get { from, key, is_expr, weak: _ } => {
-                            let from = eval(Arc::clone(&env), vec![from]);
-                            if !is_expr {
-                                match *from {
-                                    RuntimeValue::WithEnv { env, value: _ } => {
-                                        match *key {
-                                            Expr::Variable(variable) => env.write().unwrap().set(*variable, *y),
-                                            _ => panic!("only token can be assign")
-                                        }
-                                    }
-                                    _ => panic!("only object can be assign value")
-                                }
-                            } else {
-                                panic!("not implement assign to expr")
-                            }
-                        }
-                        _ => panic!("assign not impl")
-                    };
-                    b(RuntimeValue::None)
-                }
-                Op::Add => {
-                    let x = eval(Arc::clone(&env), vec![x]);
-                    let y = eval(Arc::clone(&env), vec![y]);
-                    match *x {
-                        RuntimeValue::Int64(x) => match *y {
-                            RuntimeValue::Int64(y) => b(RuntimeValue::Int64(x + y)),
-                            RuntimeValue::Int128(y) => b(RuntimeValue::Int128(i128::from(x) + y)),
-                            _ => panic!("{x:?} + {y:?} not impl")
-                        },
-                        RuntimeValue::Int128(x) => match *y {
-                            RuntimeValue::Int64(y) => b(RuntimeValue::Int128(x + i128::from(y))),
-                            RuntimeValue::Int128(y) => b(RuntimeValue::Int128(x + y)),
-                            _ => panic!("{x:?} + {y:?} not impl")
-                        },
-                        RuntimeValue::String(x) => match *y {
-                            RuntimeValue::String(y) => b(RuntimeValue::String(b(*x + y.as_str()))),
-                            _ => panic!("{x:?} + {y:?} not impl")
-                        }
-                        _ => panic!("{x:?} + {y:?} not impl")
-                    }
-                }
-                Op::Sub => {
-                    let x = eval(Arc::clone(&env), vec![x]);
-                    let y = eval(Arc::clone(&env), vec![y]);
-                    match *x {
-                        RuntimeValue::Int64(x) => match *y {
-                            RuntimeValue::Int64(y) => b(RuntimeValue::Int64(x - y)),
-                            RuntimeValue::Int128(y) => b(RuntimeValue::Int128(i128::from(x) - y)),
-                            _ => panic!("add not impl")
-                        },
-                        RuntimeValue::Int128(x) => match *y {
-                            RuntimeValue::Int64(y) => b(RuntimeValue::Int128(x - i128::from(y))),
-                            RuntimeValue::Int128(y) => b(RuntimeValue::Int128(x - y)),
-                            _ => panic!("add not impl")
-                        },
-                        _ => panic!("add not impl")
-                    }
+                    let y = propagate!(eval(Arc::clone(&env), vec![y]));
+                    runtime_assign(Arc::clone(&env), &x, *y)
                 }
-                Op::Mul => {
-                    let x = eval(Arc::clone(&env), vec![x]);
-                    let y = eval(Arc::clone(&env), vec![y]);
-                    match *x {
-                        RuntimeValue::Int64(x) => match *y {
-                            RuntimeValue::Int64(y) => b(RuntimeValue::Int64(x * y)),
-                            RuntimeValue::Int128(y) => b(RuntimeValue::Int128(i128::from(x) * y)),
-                            _ => panic!("add not impl")
-                        },
-                        RuntimeValue::Int128(x) => match *y {
-                            RuntimeValue::Int64(y) => b(RuntimeValue::Int128(x * i128::from(y))),
-                            RuntimeValue::Int128(y) => b(RuntimeValue::Int128(x * y)),
-                            _ => panic!("add not impl")
-                        },
-                        _ => panic!("add not impl")
-                    }
-                }
-                Op::Div => {
-                    let x = eval(Arc::clone(&env), vec![x]);
-                    let y = eval(Arc::clone(&env), vec![y]);
-                    match *x {
-                        RuntimeValue::Int64(x) => match *y {
-                            RuntimeValue::Int64(y) => b(RuntimeValue::Int64(x / y)),
-                            RuntimeValue::Int128(y) => b(RuntimeValue::Int128(i128::from(x) / y)),
-                            _ => panic!("add not impl")
-                        },
-                        RuntimeValue::Int128(x) => match *y {
-                            RuntimeValue::Int64(y) => b(RuntimeValue::Int128(x / i128::from(y))),
-                            RuntimeValue::Int128(y) => b(RuntimeValue::Int128(x / y)),
-                            _ => panic!("add not impl")
-                        },
-                        _ => panic!("add not impl")
-                    }
+                Op::Add | Op::Sub | Op::Mul | Op::Div => {
+                    let x = propagate!(eval(Arc::clone(&env), vec![x]));
+                    let y = propagate!(eval(Arc::clone(&env), vec![y]));
+                    runtime_arithmetic(&env, &op, x, y)
                 }
                 Op::Map => {
-                    let x = *eval(Arc::clone(&env), vec![x]);
-                    let next_arguments;
-                    let get_iter;
-                    if let RuntimeValue::WithEnv { value, env } = x {
-                        get_iter = get_name_from_env(
-                            env,
-                            "iter".to_string(),
-                        ).unwrap();
-                        next_arguments = HashMap::from([("self".to_string(), *value)]);
-                    } else {
-                        let type_env = x.get_type(env.clone()).get_env();
-                        get_iter = get_name_from_env(type_env, "iter".to_string()).unwrap();
-                        next_arguments = HashMap::from([("self".to_string(), x.clone())]);
+                    let source = match RuntimeIter::new(Arc::clone(&env), *propagate!(eval(Arc::clone(&env), vec![x]))) {
+                        Ok(source) => source,
+                        Err(e) => return b(RuntimeValue::Error(e)),
                     };
-                    let iter = *runtime_func_call(b(get_iter), vec![], next_arguments);
-                    b(RuntimeValue::WithEnv {
-                        env: Env::from(HashMap::from([
-                            ("next".to_string(), RuntimeValue::FuncDef {
-                                parameters: vec![b("self".to_string())],
-                                body: BuiltinOrExpr::Builtin(|env| {
-                                    let iter = get_name_from_env(env.clone(), "iter".to_string()).unwrap();
-                                    let func = get_name_from_env(env.clone(), "func".to_string()).unwrap();
-                                    let next_func = runtime_get(env.clone(), false, b(iter), b(Expr::Variable(b("next".to_string()))), false);
-                                    let value = runtime_func_call(next_func, vec![], HashMap::new());
-                                    *runtime_func_call(
-                                        b(func),
-                                        Vec::new(),
-                                        HashMap::from([("i".to_string(), *value)]),
-                                    )
-                                }),
-                                env: Env::from(HashMap::from([
-                                    ("iter".to_string(), iter),
-                                    ("func".to_string(), *eval(env.clone(), Vec::from([y])))
-                                ]),None),
-                            })
-                        ]), Some(env.clone())),
-                        value: b(RuntimeValue::None),
-                    })
+                    let func = *propagate!(stream_func(Arc::clone(&env), y));
+                    runtime_map(&env, source, func)
                 }
                 Op::Collect => {
-                    panic!("not impl collect")
+                    let source = match RuntimeIter::new(Arc::clone(&env), *propagate!(eval(Arc::clone(&env), vec![x]))) {
+                        Ok(source) => source,
+                        Err(e) => return b(RuntimeValue::Error(e)),
+                    };
+                    let func = *propagate!(stream_func(Arc::clone(&env), y));
+                    runtime_collect(&env, source, func)
+                }
+                Op::Eq | Op::Ne => {
+                    let x = propagate!(eval(Arc::clone(&env), vec![x]));
+                    let y = propagate!(eval(Arc::clone(&env), vec![y]));
+                    runtime_equality(&env, &op, x, y)
+                }
+                Op::Gt | Op::Ge | Op::Lt | Op::Le => {
+                    let x = propagate!(eval(Arc::clone(&env), vec![x]));
+                    let y = propagate!(eval(Arc::clone(&env), vec![y]));
+                    runtime_comparison(&env, &op, x, y)
+                }
+                Op::And => {
+                    let x = propagate!(eval(Arc::clone(&env), vec![x]));
+                    if x.is_truthy() { eval(Arc::clone(&env), vec![y]) } else { x }
                 }
-                _ => panic!("2op not impl")
+                Op::Or => {
+                    let x = propagate!(eval(Arc::clone(&env), vec![x]));
+                    if x.is_truthy() { x } else { eval(Arc::clone(&env), vec![y]) }
+                }
+                op => unsupported(&op)
             },
             Expr::FuncDef { parameters, body } => b(RuntimeValue::FuncDef {
                 parameters,
                 body: BuiltinOrExpr::Expr(body),
                 env: Env::new(Some(Arc::clone(&env))),
             }),
-            Expr::Get { from, key, is_expr, weak } => {
-                let from = eval(Arc::clone(&env), vec![from]);
-                runtime_get(env.clone(), is_expr, from, key, weak)
-            }
+            Expr::Get { from, key, is_expr, weak } => match *remove_code_pos(from) {
+                // super.method is the parent type's method, bound to the current self
+                Expr::Variable(name) if name.as_str() == "super" => {
+                    let parent = match get_name_from_env(Arc::clone(&env), "super".to_string()) {
+                        Some(RuntimeValue::RuntimeType(t)) => t,
+                        _ => return raise(RuntimeError::Name("super can only be used in a method of a type with a parent".to_string()))
+                    };
+                    let self_value = match get_self_from_env(Arc::clone(&env)) {
+                        Some(self_value) => self_value,
+                        None => return raise(RuntimeError::Name("super can only be used in a method".to_string())),
+                    };
+                    let method = match *remove_code_pos(key) {
+                        Expr::Variable(key) => match get_name_from_env(parent.get_env(), *key.clone()) {
+                            Some(method) => method,
+                            None => return raise(RuntimeError::Name(format!("type {} has no `{key}`", parent.name()))),
+                        },
+                        _ => return raise(RuntimeError::Type("can only get a method from super".to_string()))
+                    };
+                    match &method {
+                        RuntimeValue::FuncDef { parameters: _, body: _, env } => b(RuntimeValue::WithEnv {
+                            env: Env::from(HashMap::from([("self".to_string(), self_value)]), Some(env.clone())),
+                            value: b(method),
+                        }),
+                        _ => b(method)
+                    }
+                }
+                from => {
+                    let from = propagate!(eval(Arc::clone(&env), vec![b(from)]));
+                    runtime_get(env.clone(), is_expr, from, key, weak)
+                }
+            },
+            Expr::TypeDef { name, parent, body } => runtime_type_def(Arc::clone(&env), *name, parent, body),
             Expr::FuncCall { func, arguments } => {
-                let func_def = eval(Arc::clone(&env), vec![func]);
-                runtime_func_call(func_def, arguments, HashMap::new())
+                let func_def = propagate!(eval(Arc::clone(&env), vec![func]));
+                call_result(runtime_func_call(Arc::clone(&env), func_def, arguments, HashMap::new()))
+            }
+            Expr::Op1 { op, x } => {
+                let x = propagate!(eval(Arc::clone(&env), vec![x]));
+                runtime_op1(&op, x)
             }
-            _ => panic!("{ast:?} not impl")
+            Expr::Match { value, arms } => {
+                let value = propagate!(eval(Arc::clone(&env), vec![value]));
+                let mut result = b(RuntimeValue::None);
+                for arm in arms {
+                    let mut bindings = HashMap::new();
+                    match runtime_match(Arc::clone(&env), arm.pattern, &value, &mut bindings) {
+                        Ok(true) => {}
+                        Ok(false) => continue,
+                        Err(stopped) => return stopped,
+                    }
+                    // the bindings are the arm's own, other assignments in it still go to `env`
+                    let arm_env = Env::pass_through(bindings, Arc::clone(&env));
+                    let matched = match arm.guard {
+                        Some(guard) => propagate!(eval(Arc::clone(&arm_env), vec![guard])).is_truthy(),
+                        None => true,
+                    };
+                    if matched {
+                        result = eval(arm_env, vec![arm.body]);
+                        break;
+                    }
+                }
+                result
+            }
+            Expr::Import { path, alias } => import(Arc::clone(&env), *path, alias.map(|a| *a)),
+            // `break` ends the stream the current `next` or mapping function is producing
+            Expr::Control(Control::Break) => b(RuntimeValue::EOF),
+            ast => raise(RuntimeError::Type(format!("can't evaluate {}", describe(&ast))))
+        };
+        if let RuntimeValue::EOF | RuntimeValue::Error(_) = *last {
+            return last;
         }
     }
     last
 }
-
-impl VM {
-    pub fn new() -> VM {
-        VM
-    }
-
-    pub fn eval(self, global: Arc<RwLock<Env>>, ast: Vec<Box<Expr>>) -> Box<RuntimeValue> {
-        eval(global, ast)
-    }
-}
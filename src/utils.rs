@@ -1,7 +1,25 @@
+use crate::Expr;
+use crate::tf_vm::vm::remove_code_pos;
+
 pub fn b<T>(i: T) -> Box<T> {
     Box::new(i)
 }
 
+// in a pattern `{name}` is the object pattern `{name,}`, not a block
+pub fn to_pattern(expr: Box<Expr>) -> Box<Expr> {
+    match *expr {
+        Expr::ExprWithCodePos { exp, start, end } => b(Expr::ExprWithCodePos { exp: to_pattern(exp), start, end }),
+        Expr::Block(mut block) if block.len() == 1 => match *remove_code_pos(block[0].clone()) {
+            Expr::Variable(name) => b(Expr::Object(vec![(name.clone(), b(Expr::Variable(name)))])),
+            _ => b(Expr::Block(vec![block.pop().unwrap()])),
+        },
+        Expr::List(items) => b(Expr::List(items.into_iter().map(to_pattern).collect())),
+        Expr::Unpack(rest) => b(Expr::Unpack(to_pattern(rest))),
+        Expr::Object(fields) => b(Expr::Object(fields.into_iter().map(|(k, v)| (k, to_pattern(v))).collect())),
+        _ => expr,
+    }
+}
+
 pub fn slice_end_str(s: String, start: usize, pos_to_end: usize) -> String {
     let str_len = s.len();
     assert!(str_len >= start && str_len >= pos_to_end);
@@ -13,6 +31,15 @@ fn test_slice_end_str() {
     assert_eq!("asdf", slice_end_str("1asdf2".to_string(), 1, 1))
 }
 
+#[test]
+fn test_to_pattern() {
+    let parse = |code: &str| crate::text_flow::ExprsParser::new().parse(code).unwrap().pop().unwrap();
+    let strip = |expr| remove_code_pos(expr);
+    assert!(matches!(*strip(to_pattern(parse("{a}"))), Expr::Object(fields) if fields.len() == 1));
+    assert!(matches!(*strip(to_pattern(parse("{1}"))), Expr::Block(_)));
+    assert!(matches!(*strip(to_pattern(parse("[{a}, b]"))), Expr::List(items) if matches!(*strip(items[0].clone()), Expr::Object(_))));
+}
+
 #[test]
 fn test_b() {
     assert_eq!(Box::new("asdf"), b("asdf"))